@@ -1,14 +1,42 @@
 //! Common common used both by decoder and encoder
 extern crate color_quant;
 
+use std::cmp;
+use std::io;
 use std::mem;
 use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Maximum width and height of a GIF image or frame.
+///
+/// Dimensions are stored as `u16` in the GIF format.
+pub const MAX_DIMENSION: u16 = 0xFFFF;
+
+/// Converts `usize` dimensions to `u16`, failing if they exceed `MAX_DIMENSION`.
+pub fn check_dimensions(width: usize, height: usize) -> io::Result<(u16, u16)> {
+    if width > MAX_DIMENSION as usize || height > MAX_DIMENSION as usize {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "GIF dimensions must not exceed 65535x65535."
+        ))
+    } else {
+        Ok((width as u16, height as u16))
+    }
+}
+
+/// Number of bits needed to store the largest index in `data`, between 1 and 8.
+pub fn min_bit_depth(data: &[u8]) -> u8 {
+    let max = data.iter().cloned().max().unwrap_or(0);
+    cmp::max(8 - max.leading_zeros() as u8, 1)
+}
 
 /// Disposal method
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 #[repr(u8)]
 pub enum DisposalMethod {
     /// StreamingDecoder is not required to take any action.
+    ///
+    /// Viewers usually keep the frame, compositing treats it like `Keep`.
     Any = 0,
     /// Do not dispose.
     Keep = 1,
@@ -27,6 +55,11 @@ impl DisposalMethod {
             None
         }
     }
+
+    /// Value of the disposal method as stored in the control extension
+    pub fn as_u8(&self) -> u8 {
+        *self as u8
+    }
 }
 
 /// Known GIF block types
@@ -80,6 +113,110 @@ impl Extension {
     }
 }
 
+/// Application identifier of the Netscape looping extension.
+pub const APP_NETSCAPE: &'static [u8; 11] = b"NETSCAPE2.0";
+
+/// Application identifier of the ANIMEXTS looping extension.
+///
+/// Alternative to `APP_NETSCAPE` with the same semantics.
+pub const APP_ANIMEXTS: &'static [u8; 11] = b"ANIMEXTS1.0";
+
+/// Typed view of a raw RGB color palette
+///
+/// GIF palettes are stored as consecutive `[r, g, b]` triples. This wrapper
+/// provides access to the individual colors without manual index arithmetic.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Palette<'a> {
+    data: &'a [u8]
+}
+
+impl<'a> Palette<'a> {
+    /// Creates a palette from its raw byte form.
+    ///
+    /// Trailing bytes that do not form a complete color are ignored.
+    pub fn new(data: &'a [u8]) -> Palette<'a> {
+        Palette {
+            data: &data[..data.len() / 3 * 3]
+        }
+    }
+
+    /// Number of colors in the palette.
+    pub fn len(&self) -> usize {
+        self.data.len() / 3
+    }
+
+    /// True if the palette does not contain any colors.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Returns the color at `index` or `None` if it lies outside of the palette.
+    pub fn color(&self, index: usize) -> Option<[u8; 3]> {
+        if index < self.len() {
+            let c = &self.data[index * 3..];
+            Some([c[0], c[1], c[2]])
+        } else {
+            None
+        }
+    }
+
+    /// Iterator over all colors of the palette.
+    pub fn colors(&self) -> Colors<'a> {
+        Colors {
+            chunks: self.data.chunks(3)
+        }
+    }
+
+    /// Raw byte form of the palette.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+impl<'a> From<&'a [u8]> for Palette<'a> {
+    fn from(data: &'a [u8]) -> Palette<'a> {
+        Palette::new(data)
+    }
+}
+
+/// Iterator over the colors of a `Palette`
+#[derive(Debug, Clone)]
+pub struct Colors<'a> {
+    chunks: ::std::slice::Chunks<'a, u8>
+}
+
+impl<'a> Iterator for Colors<'a> {
+    type Item = [u8; 3];
+
+    fn next(&mut self) -> Option<[u8; 3]> {
+        self.chunks.next().map(|c| [c[0], c[1], c[2]])
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.chunks.size_hint()
+    }
+}
+
+/// Iterator over the pixels of a `Frame` together with their coordinates
+#[derive(Debug, Clone)]
+pub struct EnumeratePixels<'a> {
+    pixels: ::std::iter::Enumerate<::std::slice::Iter<'a, u8>>,
+    width: usize
+}
+
+impl<'a> Iterator for EnumeratePixels<'a> {
+    type Item = (u16, u16, u8);
+
+    fn next(&mut self) -> Option<(u16, u16, u8)> {
+        let width = self.width;
+        self.pixels.next().map(|(i, &idx)| ((i % width) as u16, (i / width) as u16, idx))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.pixels.size_hint()
+    }
+}
+
 /// A GIF frame
 #[derive(Debug, Clone)]
 pub struct Frame<'a> {
@@ -126,37 +263,249 @@ impl<'a> Default for Frame<'a> {
     }
 }
 
+impl<'a> Frame<'a> {
+    /// Width and height of the frame.
+    pub fn dimensions(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    /// Sets the width and height of the frame.
+    pub fn set_dimensions(&mut self, width: u16, height: u16) {
+        self.width = width;
+        self.height = height;
+    }
+
+    /// Local color palette as RGB triplets if available.
+    pub fn palette(&self) -> Option<&[u8]> {
+        self.palette.as_ref().map(|p| &**p)
+    }
+
+    /// Sets or removes the local color palette.
+    pub fn set_palette(&mut self, palette: Option<Vec<u8>>) {
+        self.palette = palette;
+    }
+
+    /// Pixel data of the frame.
+    pub fn pixels(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Iterator over the pixels as `(x, y, index)`, line by line.
+    ///
+    /// The coordinates are relative to the frame, not the logical screen. Only meaningful
+    /// for indexed pixel data.
+    pub fn enumerate_pixels(&self) -> EnumeratePixels {
+        EnumeratePixels {
+            pixels: self.buffer.iter().enumerate(),
+            width: cmp::max(self.width as usize, 1)
+        }
+    }
+
+    /// Sets the frame delay in units of 10 ms.
+    pub fn with_delay(&mut self, delay: u16) -> &mut Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Sets the disposal method.
+    pub fn with_dispose(&mut self, dispose: DisposalMethod) -> &mut Self {
+        self.dispose = dispose;
+        self
+    }
+
+    /// Sets or removes the transparent index.
+    pub fn with_transparent(&mut self, transparent: Option<u8>) -> &mut Self {
+        self.transparent = transparent;
+        self
+    }
+
+    /// Sets the offset of the frame from the left and top border of the canvas.
+    pub fn with_offset(&mut self, left: u16, top: u16) -> &mut Self {
+        self.left = left;
+        self.top = top;
+        self
+    }
+
+    /// Returns `true` if the frame has a width or height of zero and thus no pixels.
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// Typed view of the local color palette if available.
+    pub fn local_palette(&self) -> Option<Palette> {
+        self.palette.as_ref().map(|p| Palette::new(p))
+    }
+
+    /// Number of distinct color indices referenced by the pixels.
+    ///
+    /// Only meaningful for indexed pixel data.
+    pub fn used_colors(&self) -> usize {
+        self.used_indices().len()
+    }
+
+    /// Number of bits per pixel needed to store the indices of the frame, between 1 and 8.
+    ///
+    /// Derived from the largest index in the buffer, `ceil(log2(max_index + 1))`.
+    pub fn min_bit_depth(&self) -> u8 {
+        min_bit_depth(&self.buffer)
+    }
+
+    /// Distinct color indices referenced by the pixels in ascending order.
+    pub fn used_indices(&self) -> Vec<u8> {
+        let mut used = [false; 256];
+        for &idx in self.buffer.iter() {
+            used[idx as usize] = true;
+        }
+        (0..256).filter(|&i| used[i]).map(|i| i as u8).collect()
+    }
+
+    /// Removes unused entries from the local color palette.
+    ///
+    /// The pixels and the transparent index are remapped to the compacted palette,
+    /// the appearance of the frame does not change. A transparent index that is not
+    /// referenced by any pixel is removed. Frames without a local palette are left as is.
+    pub fn compact_palette(&mut self) {
+        let used = self.used_indices();
+        let mut map = [0u8; 256];
+        let compacted = match self.palette {
+            Some(ref palette) => {
+                let mut compacted = Vec::with_capacity(used.len() * 3);
+                for (new, &old) in used.iter().enumerate() {
+                    map[old as usize] = new as u8;
+                    let start = old as usize * 3;
+                    let color = palette.get(start..start + 3).unwrap_or(&[0, 0, 0]);
+                    compacted.extend(color.iter().cloned());
+                }
+                compacted
+            },
+            None => return
+        };
+        self.palette = Some(compacted);
+        self.transparent = match self.transparent {
+            Some(idx) if used.contains(&idx) => Some(map[idx as usize]),
+            _ => None
+        };
+        let buffer: Vec<u8> = self.buffer.iter().map(|&idx| map[idx as usize]).collect();
+        self.buffer = Cow::Owned(buffer);
+    }
+
+    /// Fast non-cryptographic hash (64 bit FNV-1a) of the pixel buffer.
+    ///
+    /// Frames with different hashes have different pixels. Equal hashes indicate
+    /// but do not guarantee equal pixels.
+    pub fn pixel_hash(&self) -> u64 {
+        let mut hash = 0xcbf2_9ce4_8422_2325u64;
+        for &byte in self.buffer.iter() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+        hash
+    }
+
+    /// True if `other` displays the same image at the same position and is disposed
+    /// of in the same way.
+    fn same_image(&self, other: &Frame) -> bool {
+        self.dispose == other.dispose
+        && self.left == other.left && self.top == other.top
+        && self.width == other.width && self.height == other.height
+        && self.transparent == other.transparent && self.palette == other.palette
+        && self.buffer == other.buffer
+    }
+}
+
 impl Frame<'static> {
+
+    /// Creates a frame from the sub-rectangle `rect` of an indexed canvas.
+    ///
+    /// `rect` is given as `(left, top, width, height)`. The pixels are copied into
+    /// a contiguous buffer and the frame position is set accordingly.
+    ///
+    /// Panics if `rect` does not lie within the canvas.
+    pub fn from_canvas_rect(canvas: &[u8], canvas_width: u16, rect: (u16, u16, u16, u16))
+    -> Frame<'static> {
+        let (left, top, width, height) = rect;
+        let canvas_width = canvas_width as usize;
+        assert!(left as usize + width as usize <= canvas_width);
+        assert!((top as usize + height as usize) * canvas_width <= canvas.len());
+        let mut buffer = Vec::with_capacity(width as usize * height as usize);
+        for y in top as usize..top as usize + height as usize {
+            let start = y * canvas_width + left as usize;
+            buffer.extend(canvas[start..start + width as usize].iter().cloned());
+        }
+        let mut frame = Frame::default();
+        frame.left = left;
+        frame.top = top;
+        frame.width = width;
+        frame.height = height;
+        frame.buffer = Cow::Owned(buffer);
+        frame
+    }
     
     /// Creates a frame from pixels in RGBA format.
     ///
+    /// Fully transparent pixels are excluded from the color quantization and share
+    /// a dedicated palette entry, such that they do not affect the remaining colors.
+    ///
     /// *Note: This method is not optimized for speed.*
     pub fn from_rgba(width: u16, height: u16, pixels: &mut [u8]) -> Frame<'static> {
         assert_eq!(width as usize * height as usize * 4, pixels.len());
         let mut frame = Frame::default();
-        let mut transparent = None;
+        let mut opaque = Vec::with_capacity(pixels.len());
         for pix in pixels.chunks_mut(4) {
             if pix[3] != 0 {
                 pix[3] = 0xFF;
-            } else {
-                transparent = Some([pix[0], pix[1], pix[2], pix[3]])
+                opaque.extend(pix.iter().cloned());
             }
         }
         frame.width = width;
         frame.height = height;
-        let nq = color_quant::NeuQuant::new(1, 256, pixels);
-        frame.buffer = Cow::Owned(pixels.chunks(4).map(|pix| nq.index_of(pix) as u8).collect());
-        frame.palette = Some(nq.color_map_rgb());
-        frame.transparent = if let Some(t) = transparent {
-            Some(nq.index_of(&t) as u8)
+        if opaque.len() == pixels.len() {
+            let nq = color_quant::NeuQuant::new(1, 256, pixels);
+            frame.buffer = Cow::Owned(pixels.chunks(4).map(|pix| nq.index_of(pix) as u8).collect());
+            frame.palette = Some(nq.color_map_rgb());
+        } else if opaque.is_empty() {
+            frame.buffer = Cow::Owned(vec![0; pixels.len() / 4]);
+            frame.palette = Some(vec![0, 0, 0]);
+            frame.transparent = Some(0);
         } else {
-            None
-        };
+            // Reserve the last palette entry for the transparent pixels
+            let nq = color_quant::NeuQuant::new(1, 255, &opaque);
+            let mut palette = nq.color_map_rgb();
+            let transparent = (palette.len() / 3) as u8;
+            palette.extend([0, 0, 0].iter().cloned());
+            frame.buffer = Cow::Owned(pixels.chunks(4).map(|pix| if pix[3] == 0 {
+                transparent
+            } else {
+                nq.index_of(pix) as u8
+            }).collect());
+            frame.palette = Some(palette);
+            frame.transparent = Some(transparent);
+        }
         frame
-        
-        
     }
     
+    /// Creates a frame from pixels in RGBA format with dimensions given as `usize`.
+    ///
+    /// Fails if the dimensions exceed `MAX_DIMENSION` or do not match the number of pixels.
+    pub fn try_from_rgba(width: usize, height: usize, pixels: &mut [u8])
+    -> io::Result<Frame<'static>> {
+        let (w, h) = try!(check_dimensions(width, height));
+        let len = match width.checked_mul(height).and_then(|n| n.checked_mul(4)) {
+            Some(len) => len,
+            None => return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The number of pixels exceeds the address space."
+            ))
+        };
+        if len != pixels.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The number of pixels does not match the dimensions."
+            ))
+        }
+        Ok(Frame::from_rgba(w, h, pixels))
+    }
+
     /// Creates a frame from pixels in RGB format.
     ///
     /// *Note: This method is not optimized for speed.*
@@ -168,4 +517,748 @@ impl Frame<'static> {
         }
         Frame::from_rgba(width, height, &mut vec)
     }
-}
\ No newline at end of file
+}
+
+/// Treatment of fully transparent pixels when compositing frames
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum TransparentPixels {
+    /// The canvas shows through transparent pixels, as specified by the GIF format.
+    Skip,
+    /// Transparent pixels set the canvas to the given RGBA color.
+    Clear([u8; 4]),
+}
+
+impl Default for TransparentPixels {
+    fn default() -> TransparentPixels {
+        TransparentPixels::Skip
+    }
+}
+
+/// Draws the RGBA pixels of a frame onto an RGBA canvas at the offset of `frame`.
+///
+/// `frame_rgba` holds `frame.width * frame.height` pixels, usually the buffer of a frame
+/// decoded with `ColorOutput::RGBA`. Fully transparent pixels leave the canvas untouched.
+/// Parts of the frame outside of the canvas are clipped.
+pub fn composite_frame_rgba(canvas: &mut [u8], canvas_width: u16, frame_rgba: &[u8], frame: &Frame) {
+    composite_frame_rgba_with(canvas, canvas_width, frame_rgba, frame, TransparentPixels::Skip)
+}
+
+/// Like `composite_frame_rgba` but with a configurable treatment of transparent pixels.
+pub fn composite_frame_rgba_with(canvas: &mut [u8], canvas_width: u16, frame_rgba: &[u8],
+                                 frame: &Frame, transparent: TransparentPixels) {
+    let canvas_width = canvas_width as usize;
+    let frame_width = frame.width as usize;
+    assert_eq!(frame_rgba.len(), frame_width * frame.height as usize * 4);
+    if canvas_width == 0 {
+        return
+    }
+    let canvas_height = canvas.len() / 4 / canvas_width;
+    let left = frame.left as usize;
+    let top = frame.top as usize;
+    if left >= canvas_width || top >= canvas_height {
+        return
+    }
+    let width = cmp::min(frame_width, canvas_width - left);
+    let height = cmp::min(frame.height as usize, canvas_height - top);
+    for y in 0..height {
+        let src = &frame_rgba[y * frame_width * 4..][..width * 4];
+        let dst = &mut canvas[((top + y) * canvas_width + left) * 4..][..width * 4];
+        for (dst, src) in dst.chunks_mut(4).zip(src.chunks(4)) {
+            match (src[3], transparent) {
+                (0, TransparentPixels::Skip) => (),
+                (0, TransparentPixels::Clear(color)) => dst.copy_from_slice(&color),
+                _ => dst.copy_from_slice(src)
+            }
+        }
+    }
+}
+
+/// Scales the delay of every frame by `factor`.
+///
+/// The new delays are rounded to the nearest centisecond and clamped to the range of `u16`.
+/// If `min_delay` is given, shorter delays are raised to it.
+pub fn retime(frames: &mut [Frame], factor: f32, min_delay: Option<u16>) {
+    for frame in frames.iter_mut() {
+        let delay = (frame.delay as f32 * factor).round();
+        let delay = if delay < 0.0 {
+            0
+        } else if delay > 0xFFFF as f32 {
+            0xFFFF
+        } else {
+            delay as u16
+        };
+        frame.delay = match min_delay {
+            Some(min) if delay < min => min,
+            _ => delay
+        };
+    }
+}
+
+/// Merges consecutive identical frames into one frame.
+///
+/// Frames are only merged if they also have the same disposal method. The delays of merged frames are summed up (saturating at `u16::MAX`).
+/// Frames are compared by their pixel hash first, such that only frames
+/// that are likely equal have to be compared completely.
+pub fn merge_duplicate_frames<'a>(frames: Vec<Frame<'a>>) -> Vec<Frame<'a>> {
+    let mut merged: Vec<(u64, Frame<'a>)> = Vec::with_capacity(frames.len());
+    for frame in frames {
+        let hash = frame.pixel_hash();
+        if let Some(&mut (last_hash, ref mut last)) = merged.last_mut() {
+            if last_hash == hash && last.same_image(&frame) {
+                last.delay = last.delay.saturating_add(frame.delay);
+                continue
+            }
+        }
+        merged.push((hash, frame))
+    }
+    merged.into_iter().map(|(_, frame)| frame).collect()
+}
+
+/// Encodes `cur` as the difference to `prev`, the frame displayed before it.
+///
+/// Pixels of `cur` that already show the same color once `prev` has been drawn are
+/// replaced by the transparent index and the disposal method is set to `Keep`, such that
+/// only changed pixels are stored. Pixels are compared by index if both frames use the
+/// same palette and by color if both frames have different local palettes. No pixel counts
+/// as unchanged if `prev` is disposed of with `Background` or `Previous` or if the colors
+/// cannot be compared.
+///
+/// `global_palette` is the global color table that will be written, it is used for
+/// frames without a local palette.
+///
+/// If `cur` has no transparent index, an index not used by the changed pixels is chosen,
+/// extending the local color table if necessary. A frame using the global color table
+/// only gets an unused index that lies within it. If no index is available, all pixels
+/// are stored.
+pub fn diff_encode(prev: &Frame, cur: &Frame, global_palette: Option<&[u8]>) -> Frame<'static> {
+    let mut buffer = cur.buffer.to_vec();
+    let mut palette = cur.palette.clone();
+    let mut transparent = cur.transparent;
+    let prev_palette = prev.palette.as_ref().map(|p| &p[..]).or(global_palette);
+    let cur_palette = cur.palette.as_ref().map(|p| &p[..]).or(global_palette);
+    let comparable = match prev.dispose {
+        DisposalMethod::Background | DisposalMethod::Previous => false,
+        _ => prev.palette == cur.palette || (prev_palette.is_some() && cur_palette.is_some())
+    };
+    if comparable {
+        let color = |palette: Option<&[u8]>, index: u8| match palette {
+            Some(palette) => palette.get(index as usize * 3..index as usize * 3 + 3).map(|c| c.to_vec()),
+            None => None
+        };
+        let (prev_width, prev_height) = (prev.width as usize, prev.height as usize);
+        let mut unchanged = vec![false; buffer.len()];
+        for (i, (&index, unchanged)) in buffer.iter().zip(unchanged.iter_mut()).enumerate() {
+            if Some(index) == cur.transparent {
+                continue
+            }
+            // Position inside of `prev`
+            let x = (cur.left as usize + i % cur.width as usize).wrapping_sub(prev.left as usize);
+            let y = (cur.top as usize + i / cur.width as usize).wrapping_sub(prev.top as usize);
+            if x >= prev_width || y >= prev_height {
+                continue
+            }
+            let prev_index = match prev.buffer.get(y * prev_width + x) {
+                Some(&prev_index) if Some(prev_index) != prev.transparent => prev_index,
+                // The canvas below transparent pixels is unknown
+                _ => continue
+            };
+            *unchanged = if prev.palette == cur.palette {
+                prev_index == index
+            } else {
+                let prev_color = color(prev_palette, prev_index);
+                prev_color.is_some() && prev_color == color(cur_palette, index)
+            };
+        }
+        if transparent.is_none() {
+            let mut used = [false; 256];
+            for (&index, &unchanged) in buffer.iter().zip(unchanged.iter()) {
+                if !unchanged {
+                    used[index as usize] = true
+                }
+            }
+            // Indices beyond the global color table would not be transparent
+            let available = match palette {
+                Some(_) => used.len(),
+                None => global_palette.map_or(0, |palette| palette.len() / 3)
+            };
+            transparent = used[..available].iter().position(|&used| !used).map(|index| index as u8);
+            if let (Some(index), Some(ref mut palette)) = (transparent, palette.as_mut()) {
+                while palette.len() < index as usize * 3 + 3 {
+                    palette.push(0)
+                }
+            }
+        }
+        if let Some(index) = transparent {
+            for (pixel, &unchanged) in buffer.iter_mut().zip(unchanged.iter()) {
+                if unchanged {
+                    *pixel = index
+                }
+            }
+        }
+    }
+    Frame {
+        delay: cur.delay,
+        dispose: DisposalMethod::Keep,
+        transparent: transparent,
+        needs_user_input: cur.needs_user_input,
+        top: cur.top,
+        left: cur.left,
+        width: cur.width,
+        height: cur.height,
+        interlaced: cur.interlaced,
+        palette: palette,
+        buffer: Cow::Owned(buffer)
+    }
+}
+
+/// Promotes the local palette shared by the most frames to a global palette.
+///
+/// Frames using the promoted palette have their local palette removed, the other frames
+/// are returned unchanged. A palette is only promoted if at least two frames use it,
+/// otherwise the global palette would not save any space. Nothing is promoted if any frame
+/// lacks a local palette, as such a frame already depends on the global palette.
+/// Returns the palette to be written as the global palette, if any, and the adjusted frames.
+pub fn promote_global_palette<'a>(mut frames: Vec<Frame<'a>>) -> (Option<Vec<u8>>, Vec<Frame<'a>>) {
+    if frames.iter().any(|frame| frame.palette.is_none()) {
+        return (None, frames)
+    }
+    let shared = {
+        // Number of frames using a palette and index of the first one
+        let mut counts: HashMap<&[u8], (usize, usize)> = HashMap::new();
+        for (i, frame) in frames.iter().enumerate() {
+            if let Some(ref palette) = frame.palette {
+                counts.entry(&palette[..]).or_insert((0, i)).0 += 1;
+            }
+        }
+        // Ties are broken in favor of the palette that occurs first
+        counts.into_iter()
+            .filter(|&(_, (count, _))| count > 1)
+            .max_by_key(|&(_, (count, first))| (count, usize::max_value() - first))
+            .map(|(palette, _)| palette.to_vec())
+    };
+    if let Some(ref shared) = shared {
+        for frame in frames.iter_mut() {
+            if frame.palette.as_ref() == Some(shared) {
+                frame.palette = None
+            }
+        }
+    }
+    (shared, frames)
+}
+
+/// Quantizes several RGBA frames of the same size to one shared palette.
+///
+/// The palette is trained on the pixels of all frames. Large animations are sampled
+/// such that the training cost stays close to that of a single 256x256 image. Returns
+/// the palette, to be written as the global palette, and the frames indexing into it.
+/// As in `Frame::from_rgba`, fully transparent pixels share a dedicated palette entry.
+///
+/// *Note: This method is not optimized for speed.*
+pub fn quantize_frames(width: u16, height: u16, frames: &[&[u8]]) -> (Vec<u8>, Vec<Frame<'static>>) {
+    let len = width as usize * height as usize * 4;
+    let mut opaque = Vec::with_capacity(len * frames.len());
+    let mut has_transparency = false;
+    for pixels in frames {
+        assert_eq!(len, pixels.len());
+        for pix in pixels.chunks(4) {
+            if pix[3] != 0 {
+                opaque.extend([pix[0], pix[1], pix[2], 0xFF].iter().cloned());
+            } else {
+                has_transparency = true
+            }
+        }
+    }
+    let (nq, mut palette) = if opaque.is_empty() {
+        (None, Vec::new())
+    } else {
+        let colors = if has_transparency { 255 } else { 256 };
+        // Train on about as many samples as a 256x256 image provides
+        let sample_factor = cmp::min(cmp::max(opaque.len() / 4 / (1 << 16), 1), 30) as i32;
+        let nq = color_quant::NeuQuant::new(sample_factor, colors, &opaque);
+        let palette = nq.color_map_rgb();
+        (Some(nq), palette)
+    };
+    let transparent = if has_transparency {
+        palette.extend([0, 0, 0].iter().cloned());
+        Some((palette.len() / 3 - 1) as u8)
+    } else {
+        None
+    };
+    let frames = frames.iter().map(|pixels| {
+        let mut frame = Frame::default();
+        frame.width = width;
+        frame.height = height;
+        frame.transparent = transparent;
+        frame.buffer = Cow::Owned(pixels.chunks(4).map(|pix| match (pix[3], nq.as_ref()) {
+            (0, _) | (_, None) => transparent.unwrap_or(0),
+            (_, Some(nq)) => nq.index_of(&[pix[0], pix[1], pix[2], 0xFF]) as u8
+        }).collect());
+        frame
+    }).collect();
+    (palette, frames)
+}
+
+/// Incrementally builds a palette from the colors observed in one or several images.
+///
+/// Every distinct color is counted. If more than 256 colors have been observed, the most
+/// frequent ones are kept and all others are mapped to the closest kept color.
+///
+/// *Note: This type is not optimized for speed.*
+#[derive(Debug, Default, Clone)]
+pub struct PaletteBuilder {
+    counts: HashMap<[u8; 3], usize>,
+}
+
+impl PaletteBuilder {
+    /// Creates an empty builder.
+    pub fn new() -> PaletteBuilder {
+        PaletteBuilder::default()
+    }
+
+    /// Records one occurrence of an RGB or RGBA color. The alpha channel is ignored.
+    pub fn observe(&mut self, color: &[u8]) {
+        assert!(color.len() == 3 || color.len() == 4);
+        *self.counts.entry([color[0], color[1], color[2]]).or_insert(0) += 1
+    }
+
+    /// Number of distinct colors observed so far.
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Returns `true` if no color has been observed.
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// Returns the palette and the palette index of every observed color.
+    ///
+    /// The palette holds at most 256 entries, sorted by descending frequency.
+    /// Colors observed equally often are sorted by their value.
+    pub fn build(&self) -> (Vec<u8>, HashMap<[u8; 3], u8>) {
+        let mut colors: Vec<([u8; 3], usize)> = self.counts.iter()
+            .map(|(&color, &count)| (color, count))
+            .collect();
+        colors.sort_by(|&(a, a_count), &(b, b_count)| b_count.cmp(&a_count).then(a.cmp(&b)));
+        let kept = cmp::min(colors.len(), 256);
+        let palette: Vec<u8> = colors[..kept].iter().flat_map(|&(color, _)| color.to_vec()).collect();
+        let mut indices = HashMap::with_capacity(colors.len());
+        for (i, &(color, _)) in colors[..kept].iter().enumerate() {
+            indices.insert(color, i as u8);
+        }
+        for &(color, _) in &colors[kept..] {
+            let closest = palette.chunks(3).enumerate().min_by_key(|&(_, entry)| {
+                entry.iter().zip(color.iter()).map(|(&a, &b)| {
+                    let d = a as i32 - b as i32;
+                    d * d
+                }).sum::<i32>()
+            }).map(|(i, _)| i as u8).unwrap();
+            indices.insert(color, closest);
+        }
+        (palette, indices)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+
+    use super::{Frame, Palette, DisposalMethod, PaletteBuilder, retime, merge_duplicate_frames, quantize_frames,
+        promote_global_palette, diff_encode, composite_frame_rgba, composite_frame_rgba_with, TransparentPixels};
+
+    #[test]
+    fn test_composite_frame_rgba() {
+        let background = [9, 9, 9, 0xFF];
+        let mut canvas: Vec<u8> = (0..4 * 3).flat_map(|_| background.to_vec()).collect();
+        let mut frame = Frame::default();
+        frame.width = 2;
+        frame.height = 2;
+        frame.left = 1;
+        frame.top = 1;
+        // Transparent pixel at the top right
+        let pixels = [1, 1, 1, 0xFF, 2, 2, 2, 0, 3, 3, 3, 0xFF, 4, 4, 4, 0xFF];
+        composite_frame_rgba(&mut canvas, 4, &pixels, &frame);
+        let pixel = |canvas: &[u8], x: usize, y: usize| canvas[(y * 4 + x) * 4..][..4].to_vec();
+        assert_eq!(pixel(&canvas, 1, 1), [1, 1, 1, 0xFF]);
+        assert_eq!(pixel(&canvas, 2, 1), background);
+        assert_eq!(pixel(&canvas, 1, 2), [3, 3, 3, 0xFF]);
+        assert_eq!(pixel(&canvas, 2, 2), [4, 4, 4, 0xFF]);
+        for &(x, y) in &[(0, 0), (1, 0), (2, 0), (3, 0), (0, 1), (3, 1), (0, 2), (3, 2)] {
+            assert_eq!(pixel(&canvas, x, y), background);
+        }
+
+        // Clipped at the bottom right corner
+        frame.left = 3;
+        frame.top = 2;
+        composite_frame_rgba(&mut canvas, 4, &pixels, &frame);
+        assert_eq!(pixel(&canvas, 3, 2), [1, 1, 1, 0xFF]);
+        assert_eq!(pixel(&canvas, 2, 2), [4, 4, 4, 0xFF]);
+        // Completely outside
+        frame.left = 4;
+        let before = canvas.clone();
+        composite_frame_rgba(&mut canvas, 4, &pixels, &frame);
+        assert_eq!(canvas, before);
+    }
+
+    #[test]
+    fn test_composite_transparent_pixels() {
+        let background = [9, 9, 9, 0xFF];
+        let canvas: Vec<u8> = (0..2 * 2).flat_map(|_| background.to_vec()).collect();
+        let mut frame = Frame::default();
+        frame.width = 2;
+        frame.height = 1;
+        // The right pixel is transparent
+        let pixels = [1, 1, 1, 0xFF, 2, 2, 2, 0];
+
+        let mut skipped = canvas.clone();
+        composite_frame_rgba_with(&mut skipped, 2, &pixels, &frame, TransparentPixels::default());
+        assert_eq!(&skipped[..8], &[1, 1, 1, 0xFF, 9, 9, 9, 0xFF]);
+        assert_eq!(&skipped[8..], &canvas[8..]);
+
+        let mut cleared = canvas.clone();
+        composite_frame_rgba_with(&mut cleared, 2, &pixels, &frame, TransparentPixels::Clear([0; 4]));
+        assert_eq!(&cleared[..8], &[1, 1, 1, 0xFF, 0, 0, 0, 0]);
+        assert_eq!(&cleared[8..], &canvas[8..]);
+
+        let mut cleared = canvas.clone();
+        composite_frame_rgba_with(&mut cleared, 2, &pixels, &frame, TransparentPixels::Clear([5, 6, 7, 0xFF]));
+        assert_eq!(&cleared[..8], &[1, 1, 1, 0xFF, 5, 6, 7, 0xFF]);
+    }
+
+    #[test]
+    fn test_palette_builder() {
+        let mut builder = PaletteBuilder::new();
+        assert!(builder.is_empty());
+        for _ in 0..5 {
+            builder.observe(&[0, 0xFF, 0]);
+        }
+        for _ in 0..3 {
+            builder.observe(&[0, 0, 0xFF, 0x80]);
+        }
+        builder.observe(&[0xFF, 0, 0]);
+        assert_eq!(builder.len(), 3);
+        let (palette, indices) = builder.build();
+        assert_eq!(palette, vec![0, 0xFF, 0, 0, 0, 0xFF, 0xFF, 0, 0]);
+        assert_eq!(indices[&[0, 0xFF, 0]], 0);
+        assert_eq!(indices[&[0, 0, 0xFF]], 1);
+        assert_eq!(indices[&[0xFF, 0, 0]], 2);
+
+        // 256 frequent shades of red and 256 rare colors close to them
+        let mut builder = PaletteBuilder::new();
+        for i in 0..256 {
+            builder.observe(&[i as u8, 0, 0]);
+            builder.observe(&[i as u8, 0, 0]);
+            builder.observe(&[i as u8, 0, 1]);
+        }
+        assert_eq!(builder.len(), 512);
+        let (palette, indices) = builder.build();
+        assert_eq!(palette.len(), 256 * 3);
+        assert_eq!(indices.len(), 512);
+        for i in 0..256 {
+            assert_eq!(&palette[i * 3..i * 3 + 3], &[i as u8, 0, 0]);
+            assert_eq!(indices[&[i as u8, 0, 0]], i as u8);
+            assert_eq!(indices[&[i as u8, 0, 1]], i as u8);
+        }
+    }
+
+    #[test]
+    fn test_quantize_frames() {
+        let frames: Vec<Vec<u8>> = (0..3).map(|i| (0..16 * 16).flat_map(|j| {
+            let value = ((i * 16 + j) % 64 * 4) as u8;
+            vec![value, 0xFF - value, value / 2, if j == 0 { 0 } else { 0xFF }]
+        }).collect()).collect();
+        let pixels: Vec<&[u8]> = frames.iter().map(|f| &**f).collect();
+        let (palette, quantized) = quantize_frames(16, 16, &pixels);
+        assert!(palette.len() <= 256 * 3);
+        assert_eq!(quantized.len(), 3);
+        for (frame, rgba) in quantized.iter().zip(frames.iter()) {
+            assert!(frame.palette.is_none());
+            let transparent = frame.transparent.unwrap();
+            for (&idx, pix) in frame.buffer.iter().zip(rgba.chunks(4)) {
+                if pix[3] == 0 {
+                    assert_eq!(idx, transparent);
+                    continue
+                }
+                assert!(idx != transparent);
+                let color = &palette[idx as usize * 3..][..3];
+                for c in 0..3 {
+                    assert!((color[c] as i32 - pix[c] as i32).abs() <= 8);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_accessors() {
+        let mut frame = Frame::default();
+        frame.set_dimensions(2, 1);
+        frame.set_palette(Some(vec![0, 0, 0, 0xFF, 0xFF, 0xFF]));
+        frame.buffer = Cow::Borrowed(&[1, 0]);
+        assert_eq!(frame.dimensions(), (2, 1));
+        assert_eq!((frame.width, frame.height), (2, 1));
+        assert_eq!(frame.palette(), Some(&[0, 0, 0, 0xFF, 0xFF, 0xFF][..]));
+        assert_eq!(frame.pixels(), &[1, 0]);
+        frame.set_palette(None);
+        assert!(frame.palette().is_none());
+    }
+
+    #[test]
+    fn test_fluent_setters() {
+        let mut frame = Frame::default();
+        frame
+            .with_delay(10)
+            .with_dispose(DisposalMethod::Background)
+            .with_transparent(Some(3))
+            .with_offset(5, 7);
+        assert_eq!(frame.delay, 10);
+        assert_eq!(frame.dispose, DisposalMethod::Background);
+        assert_eq!(frame.transparent, Some(3));
+        assert_eq!((frame.left, frame.top), (5, 7));
+    }
+
+    #[test]
+    fn test_used_colors() {
+        let mut frame = Frame::default();
+        frame.palette = Some(vec![0; 256 * 3]);
+        frame.buffer = Cow::Owned(vec![7, 200, 7, 0, 200, 200]);
+        assert_eq!(frame.used_colors(), 3);
+        assert_eq!(frame.used_indices(), vec![0, 7, 200]);
+    }
+
+    #[test]
+    fn test_compact_palette() {
+        let palette: Vec<u8> = (0..256 * 3).map(|i| (i / 3) as u8).collect();
+        let mut frame = Frame::default();
+        frame.palette = Some(palette.clone());
+        frame.buffer = Cow::Owned(vec![7, 200, 7, 0, 200, 200]);
+        frame.transparent = Some(200);
+        let colors = |frame: &Frame, palette: &[u8]| -> Vec<u8> {
+            frame.buffer.iter().flat_map(|&i| palette[i as usize * 3..][..3].to_vec()).collect()
+        };
+        let expected = colors(&frame, &palette);
+        frame.compact_palette();
+        let compacted = frame.palette.clone().unwrap();
+        assert_eq!(compacted.len(), 3 * 3);
+        assert_eq!(colors(&frame, &compacted), expected);
+        assert_eq!(&*frame.buffer, &[1, 2, 1, 0, 2, 2]);
+        assert_eq!(frame.transparent, Some(2));
+    }
+
+    #[test]
+    fn test_disposal_u8_round_trip() {
+        use super::DisposalMethod::*;
+        for &method in [Any, Keep, Background, Previous].iter() {
+            assert_eq!(DisposalMethod::from_u8(method.as_u8()), Some(method));
+        }
+        assert_eq!(Background.as_u8(), 2);
+        assert_eq!(DisposalMethod::from_u8(4), None);
+    }
+
+    #[test]
+    fn test_from_canvas_rect() {
+        let canvas = [
+            0, 1, 2, 3,
+            4, 5, 6, 7,
+            8, 9, 10, 11,
+        ];
+        let frame = Frame::from_canvas_rect(&canvas, 4, (1, 1, 2, 2));
+        assert_eq!((frame.left, frame.top, frame.width, frame.height), (1, 1, 2, 2));
+        assert_eq!(&*frame.buffer, &[5, 6, 9, 10][..]);
+    }
+
+    #[test]
+    fn test_palette() {
+        let palette = Palette::new(&[1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(palette.len(), 2);
+        assert_eq!(palette.color(1), Some([4, 5, 6]));
+        assert_eq!(palette.color(2), None);
+        assert_eq!(palette.colors().collect::<Vec<_>>(), vec![[1, 2, 3], [4, 5, 6]]);
+        assert_eq!(palette.as_bytes(), &[1, 2, 3, 4, 5, 6][..]);
+    }
+
+    #[test]
+    fn test_retime() {
+        let mut frames: Vec<Frame> = [3, 1, 40000].iter().map(|&delay| {
+            let mut frame = Frame::default();
+            frame.delay = delay;
+            frame
+        }).collect();
+        retime(&mut frames, 2.0, None);
+        assert_eq!(frames.iter().map(|f| f.delay).collect::<Vec<_>>(), vec![6, 2, 0xFFFF]);
+        retime(&mut frames, 0.25, None);
+        assert_eq!(frames.iter().map(|f| f.delay).collect::<Vec<_>>(), vec![2, 1, 16384]);
+        retime(&mut frames, 0.5, Some(2));
+        assert_eq!(frames.iter().map(|f| f.delay).collect::<Vec<_>>(), vec![2, 2, 8192]);
+    }
+
+    #[test]
+    fn test_from_rgba_transparency() {
+        // Left half is transparent red, right half opaque blue
+        let mut pixels: Vec<u8> = (0..100).flat_map(|i| if i % 10 < 5 {
+            vec![0xFF, 0, 0, 0]
+        } else {
+            vec![0, 0, 0xFF, 0xFF]
+        }).collect();
+        let frame = Frame::from_rgba(10, 10, &mut pixels);
+        let transparent = frame.transparent.unwrap();
+        let palette = frame.local_palette().unwrap();
+        assert_eq!(frame.buffer[0], transparent);
+        assert!(frame.buffer[5] != transparent);
+        assert_eq!(palette.color(frame.buffer[5] as usize), Some([0, 0, 0xFF]));
+    }
+
+    #[test]
+    fn test_try_from_rgba() {
+        assert!(Frame::try_from_rgba(70_000, 0, &mut []).is_err());
+        assert!(Frame::try_from_rgba(2, 2, &mut [0; 12]).is_err());
+        let frame = Frame::try_from_rgba(1, 2, &mut [0; 8]).unwrap();
+        assert_eq!((frame.width, frame.height), (1, 2));
+    }
+
+    #[test]
+    fn test_merge_duplicate_frames() {
+        let mut frame = Frame::default();
+        frame.width = 2;
+        frame.height = 1;
+        frame.delay = 10;
+        frame.buffer = Cow::Borrowed(&[1, 2]);
+        let mut other = frame.clone();
+        other.buffer = Cow::Borrowed(&[2, 1]);
+        assert_eq!(frame.pixel_hash(), frame.clone().pixel_hash());
+        assert!(frame.pixel_hash() != other.pixel_hash());
+        let merged = merge_duplicate_frames(vec![frame.clone(), frame.clone(), other]);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].delay, 20);
+        assert_eq!(&*merged[1].buffer, &[2, 1][..]);
+        // The background clear of the second frame must not be lost
+        frame.dispose = DisposalMethod::Keep;
+        let mut cleared = frame.clone();
+        cleared.dispose = DisposalMethod::Background;
+        let merged = merge_duplicate_frames(vec![frame.clone(), cleared]);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].dispose, DisposalMethod::Keep);
+        assert_eq!(merged[1].dispose, DisposalMethod::Background);
+    }
+
+    /// Draws indexed frames onto an RGBA canvas of the given width
+    fn render(canvas: &mut [u8], width: u16, frames: &[&Frame]) {
+        for frame in frames {
+            let palette = frame.palette.as_ref().unwrap();
+            let rgba: Vec<u8> = frame.buffer.iter().flat_map(|&index| {
+                let alpha = if Some(index) == frame.transparent { 0 } else { 0xFF };
+                let rgb = &palette[index as usize * 3..][..3];
+                vec![rgb[0], rgb[1], rgb[2], alpha]
+            }).collect();
+            composite_frame_rgba(canvas, width, &rgba, frame);
+        }
+    }
+
+    #[test]
+    fn test_diff_encode() {
+        let mut prev = Frame::default();
+        prev.width = 3;
+        prev.height = 2;
+        prev.buffer = Cow::Borrowed(&[0, 1, 2, 2, 1, 0]);
+        prev.palette = Some(vec![0, 0, 0, 1, 1, 1, 2, 2, 2]);
+        let mut cur = prev.clone();
+        cur.buffer = Cow::Borrowed(&[0, 2, 2, 1, 1, 0]);
+        // Drawing the difference after `prev` has the same result as drawing `cur`
+        let check = |prev: &Frame, cur: &Frame| {
+            let diff = diff_encode(prev, cur, None);
+            assert_eq!(diff.dispose, DisposalMethod::Keep);
+            let mut expected = vec![0; 3 * 2 * 4];
+            render(&mut expected, 3, &[prev, cur]);
+            let mut canvas = vec![0; 3 * 2 * 4];
+            render(&mut canvas, 3, &[prev, &diff]);
+            assert_eq!(canvas, expected);
+            diff
+        };
+        // A transparent index not used by the changed pixels is allocated
+        let diff = check(&prev, &cur);
+        assert_eq!(diff.transparent, Some(0));
+        assert_eq!(&*diff.buffer, &[0, 2, 0, 1, 0, 0][..]);
+        // An existing transparent index is reused
+        cur.transparent = Some(2);
+        cur.buffer = Cow::Borrowed(&[0, 1, 1, 1, 1, 0]);
+        let diff = check(&prev, &cur);
+        assert_eq!(&*diff.buffer, &[2, 2, 1, 1, 2, 2][..]);
+        // Different local palettes are compared by color, the palette is extended
+        // as the changed pixels use all of its entries
+        cur.transparent = None;
+        cur.buffer = Cow::Borrowed(&[2, 0, 2, 1, 0, 0]);
+        cur.palette = Some(vec![1, 1, 1, 0, 0, 0, 2, 2, 2]);
+        let diff = check(&prev, &cur);
+        assert_eq!(diff.transparent, Some(3));
+        assert_eq!(diff.palette, Some(vec![1, 1, 1, 0, 0, 0, 2, 2, 2, 0, 0, 0]));
+        assert_eq!(&*diff.buffer, &[2, 3, 3, 1, 3, 0][..]);
+        // The canvas is unknown after disposing of `prev`
+        prev.dispose = DisposalMethod::Background;
+        assert_eq!(&*diff_encode(&prev, &cur, None).buffer, &*cur.buffer);
+    }
+
+    #[test]
+    fn test_diff_encode_global_palette() {
+        let global = [0, 0, 0, 1, 1, 1];
+        let mut prev = Frame::default();
+        prev.width = 3;
+        prev.height = 1;
+        prev.buffer = Cow::Borrowed(&[0, 1, 1]);
+        let mut cur = prev.clone();
+        cur.buffer = Cow::Borrowed(&[1, 0, 1]);
+        // The changed pixels use the whole global table, no index beyond it is allocated
+        let diff = diff_encode(&prev, &cur, Some(&global));
+        assert_eq!(diff.transparent, None);
+        assert_eq!(diff.palette, None);
+        assert_eq!(&*diff.buffer, &*cur.buffer);
+        // Without the global table no index can be checked
+        cur.buffer = Cow::Borrowed(&[0, 1, 0]);
+        assert_eq!(diff_encode(&prev, &cur, None).transparent, None);
+        // An unused index within the global table is allocated
+        let diff = diff_encode(&prev, &cur, Some(&global));
+        assert_eq!(diff.transparent, Some(1));
+        assert_eq!(&*diff.buffer, &[1, 1, 0][..]);
+        // A local palette is compared by color against the global table
+        cur.palette = Some(vec![1, 1, 1, 0, 0, 0]);
+        cur.buffer = Cow::Borrowed(&[1, 0, 1]);
+        let diff = diff_encode(&prev, &cur, Some(&global));
+        assert_eq!(diff.transparent, Some(0));
+        assert_eq!(&*diff.buffer, &[0, 0, 1][..]);
+    }
+
+    #[test]
+    fn test_min_bit_depth() {
+        let mut frame = Frame::default();
+        for &(max, depth) in &[(0, 1), (1, 1), (2, 2), (3, 2), (4, 3), (16, 5), (128, 8), (255, 8)] {
+            frame.buffer = Cow::Owned(vec![max / 2, max, 0]);
+            assert_eq!(frame.min_bit_depth(), depth);
+        }
+        frame.buffer = Cow::Borrowed(&[]);
+        assert_eq!(frame.min_bit_depth(), 1);
+    }
+
+    #[test]
+    fn test_promote_global_palette() {
+        let mut frame = Frame::default();
+        frame.width = 2;
+        frame.height = 1;
+        frame.buffer = Cow::Borrowed(&[0, 1]);
+        frame.palette = Some(vec![1, 2, 3, 4, 5, 6]);
+        let mut other = frame.clone();
+        other.palette = Some(vec![6, 5, 4, 3, 2, 1]);
+        let frames = vec![other.clone(), frame.clone(), frame.clone(), other.clone(), frame.clone()];
+        let (global, frames) = promote_global_palette(frames);
+        assert_eq!(global, Some(vec![1, 2, 3, 4, 5, 6]));
+        let palettes: Vec<_> = frames.iter().map(|frame| frame.palette.clone()).collect();
+        assert_eq!(palettes, vec![other.palette.clone(), None, None, other.palette.clone(), None]);
+        // Palettes used by a single frame stay local
+        let (global, frames) = promote_global_palette(vec![frame.clone(), other.clone()]);
+        assert_eq!(global, None);
+        assert!(frames.iter().all(|frame| frame.palette.is_some()));
+        // Frames relying on an existing global palette prevent the promotion
+        let mut global_only = frame.clone();
+        global_only.palette = None;
+        let (global, frames) = promote_global_palette(vec![frame.clone(), frame.clone(), global_only]);
+        assert_eq!(global, None);
+        assert_eq!(frames[0].palette, frame.palette);
+    }
+}
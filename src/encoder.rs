@@ -3,16 +3,38 @@
 
 
 
-use std::cmp::min;
+use std::borrow::Cow;
+use std::cmp::{min, max};
 use std::io;
 use std::io::prelude::*;
+use std::time::Duration;
 
 use lzw;
 
-use traits::WriteBytesExt;
-use common::{Block, Frame, Extension, DisposalMethod};
+use traits::{WriteBytesExt, Parameter, SetParameter};
+use common::{Block, Frame, Extension, DisposalMethod, PaletteBuilder, APP_NETSCAPE, check_dimensions, min_bit_depth};
+use common::quantize_frames;
+use text::PlainText;
 use util;
 
+/// Configures whether color tables are flagged as sorted
+///
+/// A sorted color table lists its colors in order of decreasing importance
+/// which can assist decoders with fewer available colors.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum PaletteOrder {
+    /// The color tables are not flagged as sorted.
+    Unsorted,
+    /// The color tables are flagged as sorted, most important color first.
+    Sorted,
+}
+
+impl<W: Write> Parameter<Encoder<W>> for PaletteOrder {
+    fn set_param(self, this: &mut Encoder<W>) {
+        this.palette_order = self
+    }
+}
+
 /// Extension data.
 pub enum ExtensionData {
     /// Control extension
@@ -39,7 +61,7 @@ impl ExtensionData {
 			None => 0
 		};
 		flags |= (needs_user_input as u8) << 1;
-		flags |= (dispose as u8) << 2;
+		flags |= dispose.as_u8() << 2;
 		ExtensionData::Control {
 			flags: flags,
 			delay: delay,
@@ -105,27 +127,349 @@ impl<'a, W: Write + 'a> Drop for BlockWriter<'a, W> {
 	}
 }
 
+/// Palette layout of an animation written by `encode_animation`
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum AnimationPalette {
+    /// Every frame is quantized to its own local palette.
+    PerFrame,
+    /// All frames are quantized to one global palette using `quantize_frames`.
+    Shared,
+}
+
+/// Encodes RGBA frames as a looping animation
+///
+/// Every frame in `frames` consists of `width * height` RGBA pixels and the time it is
+/// displayed, which is rounded down to hundredths of a second. Each frame replaces the
+/// previous one completely. A `loop_count` of 0 means infinite looping.
+///
+/// With `AnimationPalette::PerFrame` every frame is quantized to a local palette of up to
+/// 256 colors and no global palette is written. This gives the best color quality for
+/// animations whose frames differ a lot, but quantization is the slowest part of the
+/// encoding and each palette adds up to 768 bytes to the file. With
+/// `AnimationPalette::Shared` the palette is trained once on all frames and written as the
+/// global palette. This is faster and smaller, at the cost of fewer colors per frame.
+pub fn encode_animation<W: Write>(w: W, width: u16, height: u16,
+                                  frames: &[(Vec<u8>, Duration)], loop_count: u16,
+                                  palette: AnimationPalette)
+-> io::Result<()> {
+	if frames.iter().any(|&(ref pixels, _)| pixels.len() != width as usize * height as usize * 4) {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"The number of pixels does not match the dimensions."
+		))
+	}
+	let (mut encoder, quantized) = match palette {
+		AnimationPalette::PerFrame => {
+			let quantized = frames.iter().map(
+				|&(ref pixels, _)| Frame::from_rgba(width, height, &mut pixels.clone())
+			).collect();
+			(try!(Encoder::new(w, width, height).write_header()), quantized)
+		},
+		AnimationPalette::Shared => {
+			let pixels: Vec<&[u8]> = frames.iter().map(|&(ref pixels, _)| &**pixels).collect();
+			let (global, quantized) = quantize_frames(width, height, &pixels);
+			(try!(Encoder::new(w, width, height).write_global_palette(&global)), quantized)
+		}
+	};
+	try!(encoder.write_loop_count(loop_count));
+	for (mut frame, &(_, duration)) in quantized.into_iter().zip(frames.iter()) {
+		let delay = duration.as_secs() * 100 + (duration.subsec_nanos() / 10_000_000) as u64;
+		frame.delay = min(delay, u16::max_value() as u64) as u16;
+		frame.dispose = DisposalMethod::Background;
+		try!(encoder.write_frame(&frame));
+	}
+	Ok(())
+}
+
+/// Encodes an RGBA still image with more than 256 colors without quantizing it
+///
+/// The image is split into tiles of at most 256 colors each, which are written as
+/// frames with their own local palette and a delay of 0. Tiles with too many colors are
+/// halved until their colors fit, at the latest a 16x16 tile does. Fully transparent
+/// pixels are kept transparent. Each palette adds up to 768 bytes to the file, so this
+/// is only worth it for images that really need the colors. Note that some viewers
+/// display frames with a delay of 0 with a short delay nevertheless.
+pub fn encode_truecolor_still<W: Write>(w: W, width: u16, height: u16, rgba: &[u8])
+-> io::Result<()> {
+	if rgba.len() != width as usize * height as usize * 4 {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"The number of pixels does not match the dimensions."
+		))
+	}
+	let mut encoder = try!(Encoder::new(w, width, height).write_global_palette(&[]));
+	// Tiles left to encode as (left, top, width, height), the next one is last
+	let mut tiles = vec![(0, 0, width, height)];
+	while let Some((left, top, tile_width, tile_height)) = tiles.pop() {
+		let pixels: Vec<&[u8]> = (top..top + tile_height).flat_map(|y| {
+			let start = (y as usize * width as usize + left as usize) * 4;
+			rgba[start..start + tile_width as usize * 4].chunks(4)
+		}).collect();
+		let mut builder = PaletteBuilder::new();
+		let mut has_transparency = false;
+		for pix in &pixels {
+			if pix[3] == 0 {
+				has_transparency = true
+			} else {
+				builder.observe(pix)
+			}
+		}
+		let max_colors = if has_transparency { 255 } else { 256 };
+		if builder.len() > max_colors {
+			// Split the longer side, the left or upper half is encoded first
+			if tile_width >= tile_height {
+				let half = tile_width / 2;
+				tiles.push((left + half, top, tile_width - half, tile_height));
+				tiles.push((left, top, half, tile_height));
+			} else {
+				let half = tile_height / 2;
+				tiles.push((left, top + half, tile_width, tile_height - half));
+				tiles.push((left, top, tile_width, half));
+			}
+			continue
+		}
+		let (mut palette, indices) = builder.build();
+		let transparent = (palette.len() / 3) as u8;
+		let mut frame = Frame::default();
+		frame.left = left;
+		frame.top = top;
+		frame.width = tile_width;
+		frame.height = tile_height;
+		frame.buffer = Cow::Owned(pixels.iter().map(|pix| if pix[3] == 0 {
+			transparent
+		} else {
+			indices[&[pix[0], pix[1], pix[2]]]
+		}).collect());
+		if has_transparency {
+			palette.extend([0, 0, 0].iter().cloned());
+			frame.transparent = Some(transparent);
+		}
+		frame.palette = Some(palette);
+		try!(encoder.write_frame(&frame));
+	}
+	Ok(())
+}
+
+/// Checks that `size` is a valid minimum code size for the indices in `data`.
+fn check_min_code_size(size: u8, data: &[u8]) -> io::Result<()> {
+	if size < 2 || size > 8 {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"The minimum code size has to be between 2 and 8."
+		))
+	}
+	if data.iter().any(|&idx| idx as u16 >> size != 0) {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"The minimum code size is too small for the image data."
+		))
+	}
+	Ok(())
+}
+
+/// Unpacks `depth` bits per pixel indices with padded lines to one byte per pixel.
+fn unpack_indices(data: &[u8], width: usize, height: usize, depth: u8) -> io::Result<Vec<u8>> {
+	match depth {
+		1 | 2 | 4 | 8 => (),
+		_ => return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"Packed indices must have a depth of 1, 2, 4 or 8 bits."
+		))
+	}
+	let depth = depth as usize;
+	let line_length = (width * depth + 7) / 8;
+	if data.len() != line_length * height {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"The length of the packed image data does not match the dimensions."
+		))
+	}
+	let mask = ((1u16 << depth) - 1) as u8;
+	let mut result = Vec::with_capacity(width * height);
+	for line in data.chunks(max(line_length, 1)).take(height) {
+		for x in 0..width {
+			let bit = x * depth;
+			let shift = 8 - depth - bit % 8;
+			result.push((line[bit / 8] >> shift) & mask)
+		}
+	}
+	Ok(result)
+}
+
+/// Reorders the lines of an image into the order of the four interlacing passes.
+fn interlace(data: &[u8], width: usize, height: usize) -> Vec<u8> {
+	let lines: Vec<&[u8]> = data.chunks(max(width, 1)).take(height).collect();
+	let mut result = Vec::with_capacity(data.len());
+	for &(start, step) in &[(0, 8), (4, 8), (2, 4), (1, 2)] {
+		let mut y = start;
+		while y < lines.len() {
+			result.extend(lines[y].iter().cloned());
+			y += step;
+		}
+	}
+	result
+}
+
+/// Writer that discards the data and counts the bytes
+struct ByteCounter(usize);
+
+impl Write for ByteCounter {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.0 += buf.len();
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
 /// Wrapper for `Encoder` that indicates that the file headers have been written.
 pub struct HeaderWritten<W: Write> {
-	enc: Encoder<W>
+	// Only `None` after `into_inner` has taken the writer
+	enc: Option<Encoder<W>>
 }
 
 impl<W: Write> HeaderWritten<W> {
 	/// Writes a complete frame to the image
 	///
+	/// The image data is compressed straight from `frame.buffer`, a borrowed buffer
+	/// is never copied.
+	///
 	/// Note: This function also writes a control extention if necessary.
 	pub fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
-		self.enc.write_frame(frame)
+		self.enc_mut().write_frame(frame)
+	}
+
+	/// Writes a frame either interlaced or not, whichever is smaller
+	///
+	/// The order of the lines influences the LZW compression. The frame is encoded both
+	/// ways and the smaller encoding is written, `frame.interlaced` is ignored. Returns
+	/// `true` if the interlaced encoding has been written.
+	pub fn write_frame_best_interlacing(&mut self, frame: &Frame) -> io::Result<bool> {
+		let palette = frame.palette.as_ref().map(|p| &**p);
+		let mut encodings = Vec::with_capacity(2);
+		for &interlaced in &[false, true] {
+			let mut enc = self.enc().with_writer(Vec::new());
+			try!(enc.write_frame_parts(frame, palette, &frame.buffer, interlaced));
+			encodings.push(enc.w);
+		}
+		let interlaced = encodings[1].len() < encodings[0].len();
+		try!(self.enc_mut().w.write_all(&encodings[interlaced as usize]));
+		Ok(interlaced)
+	}
+
+	/// Number of bytes `write_frame` would write for `frame`
+	///
+	/// The frame is encoded with the current settings into a sink that only counts
+	/// the bytes. The size includes the control extension and the local color table.
+	/// Errors are the ones `write_frame` would report.
+	pub fn frame_encoded_size(&self, frame: &Frame) -> io::Result<usize> {
+		let mut enc = self.enc().with_writer(ByteCounter(0));
+		try!(enc.write_frame(frame));
+		Ok(enc.w.0)
+	}
+
+	/// Writes an indexed image without constructing a `Frame`
+	///
+	/// `data` contains the color indices of the image, `palette` an optional local color
+	/// palette. The remaining settings of the frame are taken from `opts`. The number of
+	/// indices has to match the dimensions.
+	pub fn write_indexed_frame(&mut self, width: u16, height: u16, data: &[u8],
+	                           palette: Option<&[u8]>, opts: &FrameOptions) -> io::Result<()> {
+		self.enc_mut().write_indexed_frame(width, height, data, palette, opts)
+	}
+
+	/// Writes an indexed image whose indices are packed at `depth` bits per pixel
+	///
+	/// `depth` has to be 1, 2, 4 or 8. The first pixel is stored in the most significant
+	/// bits of a byte and every line is padded to a full byte, which is the layout produced
+	/// by `ColorOutput::PackedIndexed`. Data of any other length is rejected.
+	pub fn write_packed_frame(&mut self, width: u16, height: u16, data: &[u8], depth: u8,
+	                          palette: Option<&[u8]>, opts: &FrameOptions) -> io::Result<()> {
+		let data = try!(unpack_indices(data, width as usize, height as usize, depth));
+		self.enc_mut().write_indexed_frame(width, height, &data, palette, opts)
 	}
 
 	/// Writes an extension to the image
 	pub fn write_extension(&mut self, extension: ExtensionData) -> io::Result<()> {
-		self.enc.write_extension(extension)
+		self.enc_mut().write_extension(extension)
+	}
+
+	/// Writes the Netscape looping extension. A loop count of 0 means infinite looping.
+	pub fn write_loop_count(&mut self, count: u16) -> io::Result<()> {
+		self.enc_mut().write_app_loop_count(APP_NETSCAPE, count)
+	}
+
+	/// Writes a looping extension with the given application identifier
+	///
+	/// `APP_NETSCAPE` and `APP_ANIMEXTS` are recognized by decoders.
+	pub fn write_app_loop_count(&mut self, identifier: &[u8; 11], count: u16) -> io::Result<()> {
+		self.enc_mut().write_app_loop_count(identifier, count)
+	}
+
+	/// Writes the buffering sub-block variant of a looping extension
+	///
+	/// `size` is the number of bytes the decoder should buffer before displaying the image.
+	pub fn write_app_buffer_size(&mut self, identifier: &[u8; 11], size: u32) -> io::Result<()> {
+		self.enc_mut().write_app_buffer_size(identifier, size)
+	}
+
+	/// Writes the trailer which terminates the image
+	///
+	/// With `TrailerMode::Automatic` the trailer is not written again on drop.
+	pub fn write_trailer(&mut self) -> io::Result<()> {
+		try!(self.enc_mut().w.write_le(Block::Trailer as u8));
+		self.enc_mut().trailer_written = true;
+		Ok(())
 	}
 
 	/// Writes a raw extension to the image
 	pub fn write_raw_extension(&mut self, func: u8, data: &[u8]) -> io::Result<()> {
-		self.enc.write_raw_extension(func, data)
+		self.enc_mut().write_raw_extension(func, data)
+	}
+
+	/// Writes an extension consisting of the given data sub-blocks
+	///
+	/// Unlike `write_raw_extension` the chunking of the data is preserved, as returned
+	/// by `Reader::raw_extension_sub_blocks`. Sub-blocks have to hold 1 to 255 bytes.
+	pub fn write_extension_sub_blocks(&mut self, func: u8, sub_blocks: &[Vec<u8>]) -> io::Result<()> {
+		self.enc_mut().write_extension_sub_blocks(func, sub_blocks)
+	}
+
+	/// Writes a plain text extension
+	pub fn write_plain_text(&mut self, text: &PlainText) -> io::Result<()> {
+		let enc = self.enc_mut();
+		try!(enc.w.write_le(Block::Extension as u8));
+		try!(enc.w.write_le(Extension::Text as u8));
+		try!(enc.w.write_le(12u8));
+		try!(enc.w.write_all(&text.parameters()));
+		for chunk in text.text.chunks(0xFF) {
+			try!(enc.w.write_le(chunk.len() as u8));
+			try!(enc.w.write_all(chunk));
+		}
+		enc.w.write_le(0u8)
+	}
+
+	/// Finishes the image and returns the underlying writer
+	///
+	/// The trailer is written unless it has been written already or
+	/// `TrailerMode::Suppressed` is set. Unlike on drop, errors are reported.
+	pub fn into_inner(mut self) -> io::Result<W> {
+		if self.enc().trailer_mode == TrailerMode::Automatic && !self.enc().trailer_written {
+			try!(self.write_trailer());
+		}
+		try!(self.enc_mut().w.flush());
+		// The trailer has been handled, `drop` has nothing left to do
+		Ok(self.enc.take().expect("encoder is only taken here").w)
+	}
+
+	fn enc(&self) -> &Encoder<W> {
+		self.enc.as_ref().expect("encoder is only taken by into_inner")
+	}
+
+	fn enc_mut(&mut self) -> &mut Encoder<W> {
+		self.enc.as_mut().expect("encoder is only taken by into_inner")
 	}
 }
 
@@ -133,12 +477,20 @@ impl<W: Write> Drop for HeaderWritten<W> {
 
     #[cfg(feature = "raii_no_panic")]
 	fn drop(&mut self) {
-		let _ = self.enc.w.write_le(Block::Trailer as u8);
+		if let Some(ref mut enc) = self.enc {
+			if enc.trailer_mode == TrailerMode::Automatic && !enc.trailer_written {
+				let _ = enc.w.write_le(Block::Trailer as u8);
+			}
+		}
 	}
 
     #[cfg(not(feature = "raii_no_panic"))]
 	fn drop(&mut self) {
-		self.enc.w.write_le(Block::Trailer as u8).unwrap()
+		if let Some(ref mut enc) = self.enc {
+			if enc.trailer_mode == TrailerMode::Automatic && !enc.trailer_written {
+				enc.w.write_le(Block::Trailer as u8).unwrap()
+			}
+		}
 	}
 }
 
@@ -160,37 +512,342 @@ pub struct Frame {
 
 */
 
+/// Configures whether frames are restricted to the global color palette
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum PaletteLock {
+    /// Frames may bring their own local color palette.
+    Unlocked,
+    /// Frames must not have a local palette and may only reference colors
+    /// of the global palette. Violating frames are rejected by `write_frame`.
+    Locked,
+}
+
+impl<W: Write> Parameter<Encoder<W>> for PaletteLock {
+    fn set_param(self, this: &mut Encoder<W>) {
+        this.palette_lock = self
+    }
+}
+
+/// Disposal method used for frames that leave their disposal at `DisposalMethod::Any`
+#[derive(Debug, Copy, Clone)]
+pub struct DefaultDisposal(pub DisposalMethod);
+
+impl<W: Write> Parameter<Encoder<W>> for DefaultDisposal {
+    fn set_param(self, this: &mut Encoder<W>) {
+        this.default_dispose = self.0
+    }
+}
+
+/// Determines how the minimum LZW code size of the image data is chosen
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum MinCodeSize {
+    /// The code size is derived from the largest index found in the image data.
+    FromData,
+    /// The code size is derived from the size of the palette of the frame.
+    ///
+    /// Avoids scanning the image data of every frame, which is redundant for animations
+    /// sharing one palette. Frames with indices that do not fit into the code size are
+    /// rejected.
+    FromPalette,
+    /// The given code size is used for every frame.
+    ///
+    /// Allows to reproduce the output of other encoders. The code size has to be
+    /// within 2 to 8 and large enough for the largest index of every frame,
+    /// otherwise the frame is rejected.
+    Fixed(u8),
+}
+
+impl<W: Write> Parameter<Encoder<W>> for MinCodeSize {
+    fn set_param(self, this: &mut Encoder<W>) {
+        this.min_code_size = self
+    }
+}
+
+/// Configures whether the trailer is written when `HeaderWritten` is dropped
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum TrailerMode {
+    /// The trailer is written on drop unless `HeaderWritten::write_trailer` has been called.
+    Automatic,
+    /// The trailer is never written on drop.
+    ///
+    /// The caller has to write it using `HeaderWritten::write_trailer`, otherwise
+    /// the resulting image is incomplete. Useful when composing several GIF segments.
+    Suppressed,
+}
+
+impl<W: Write> Parameter<Encoder<W>> for TrailerMode {
+    fn set_param(self, this: &mut Encoder<W>) {
+        this.trailer_mode = self
+    }
+}
+
+/// Configures how color palettes with fewer entries than their color table are written
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum PaletteLength {
+    /// The color table is padded with black entries to the next power of two.
+    Padded,
+    /// The palette is written exactly as given.
+    ///
+    /// Palettes which do not fill a color table of 2, 4, …, 256 entries are rejected
+    /// instead of being padded. This guarantees that a palette obtained from a decoder
+    /// is reproduced byte by byte.
+    Exact,
+}
+
+impl<W: Write> Parameter<Encoder<W>> for PaletteLength {
+    fn set_param(self, this: &mut Encoder<W>) {
+        this.palette_length = self
+    }
+}
+
+/// Determines how the code stream of the image data is produced
+#[derive(Debug, Copy, Clone)]
+pub enum Compression {
+    /// The image data is LZW compressed.
+    Lzw,
+    /// The dictionary is cleared before the codes would grow beyond the minimum code
+    /// size, such that every index is stored as a code of its own.
+    ///
+    /// Produces larger files which are compatible with decoders that mishandle
+    /// growing code sizes.
+    Uncompressed,
+    /// The code stream is produced by the given function.
+    ///
+    /// The function receives the indices of the frame and the minimum code size and has
+    /// to return a complete code stream, starting with a clear code and ending with the
+    /// end code. It is split into sub-blocks by the encoder.
+    Custom(fn(&[u8], u8) -> Vec<u8>),
+}
+
+impl<W: Write> Parameter<Encoder<W>> for Compression {
+    fn set_param(self, this: &mut Encoder<W>) {
+        this.compression = self
+    }
+}
+
+/// Settings of a frame written by `HeaderWritten::write_indexed_frame`
+///
+/// Mirrors the fields of `Frame` besides its dimensions, palette and pixels. The default
+/// matches `Frame::default`.
+#[derive(Debug, Copy, Clone)]
+pub struct FrameOptions {
+    /// Frame delay.
+    pub delay: u16,
+    /// Disposal method.
+    pub dispose: DisposalMethod,
+    /// Transparent index (if available).
+    pub transparent: Option<u8>,
+    /// True if the frame needs user input do be displayed.
+    pub needs_user_input: bool,
+    /// Offset from the top border of the canvas.
+    pub top: u16,
+    /// Offset from the left border of the canvas.
+    pub left: u16,
+    /// True if the image is written interlaced.
+    pub interlaced: bool,
+}
+
+impl Default for FrameOptions {
+    fn default() -> FrameOptions {
+        FrameOptions {
+            delay: 0,
+            dispose: DisposalMethod::Keep,
+            transparent: None,
+            needs_user_input: false,
+            top: 0,
+            left: 0,
+            interlaced: false
+        }
+    }
+}
+
+/// Produces a code stream for `Compression::Uncompressed`
+fn encode_uncompressed(data: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear_code = 1u32 << min_code_size;
+    let code_size = min_code_size + 1;
+    // The code size of the decoder grows once the dictionary holds 2 ^ code_size - 1
+    // entries, every code after the first one adds an entry
+    let run = (1 << min_code_size) - 2;
+    let mut result = Vec::with_capacity(data.len() * code_size as usize / 8 + 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    {
+        let mut write_code = |code: u32| {
+            acc |= code << bits;
+            bits += code_size;
+            while bits >= 8 {
+                result.push(acc as u8);
+                acc >>= 8;
+                bits -= 8;
+            }
+        };
+        write_code(clear_code);
+        for (i, chunk) in data.chunks(run).enumerate() {
+            if i > 0 {
+                write_code(clear_code);
+            }
+            for &idx in chunk {
+                write_code(idx as u32);
+            }
+        }
+        write_code(clear_code + 1);
+    }
+    if bits > 0 {
+        result.push(acc as u8);
+    }
+    result
+}
+
 /// GIF encoder.
 pub struct Encoder<W: Write> {
     w: W,
     global_palette: bool,
+    global_colors: usize,
+    palette_order: PaletteOrder,
+    palette_lock: PaletteLock,
+    palette_length: PaletteLength,
+    default_dispose: DisposalMethod,
+    background_index: u8,
+    aspect_ratio: u8,
+    min_code_size: MinCodeSize,
+    compression: Compression,
+    trailer_mode: TrailerMode,
+    trailer_written: bool,
     width: u16,
     height: u16
 }
 
+impl<W: Write> SetParameter for Encoder<W> {}
+
 impl<W: Write> Encoder<W> {
     /// Creates a new encoder.
 	pub fn new(w: W, width: u16, height: u16) -> Self {
 		Encoder {
 			w: w,
 			global_palette: false,
+			global_colors: 0,
+			palette_order: PaletteOrder::Unsorted,
+			palette_lock: PaletteLock::Unlocked,
+			palette_length: PaletteLength::Padded,
+			default_dispose: DisposalMethod::Any,
+			background_index: 0,
+			aspect_ratio: 0,
+			min_code_size: MinCodeSize::FromData,
+			compression: Compression::Lzw,
+			trailer_mode: TrailerMode::Automatic,
+			trailer_written: false,
 			width: width,
 			height: height
 		}
 	}
 
+	/// Creates an encoder with the same settings and state writing to `w`.
+	fn with_writer<V: Write>(&self, w: V) -> Encoder<V> {
+		Encoder {
+			w: w,
+			global_palette: self.global_palette,
+			global_colors: self.global_colors,
+			palette_order: self.palette_order,
+			palette_lock: self.palette_lock,
+			palette_length: self.palette_length,
+			default_dispose: self.default_dispose,
+			background_index: self.background_index,
+			aspect_ratio: self.aspect_ratio,
+			min_code_size: self.min_code_size,
+			compression: self.compression,
+			trailer_mode: self.trailer_mode,
+			trailer_written: self.trailer_written,
+			width: self.width,
+			height: self.height
+		}
+	}
+
+	/// Creates a new encoder from dimensions given as `usize`.
+	///
+	/// Fails if the dimensions exceed `MAX_DIMENSION`.
+	pub fn try_new(w: W, width: usize, height: usize) -> io::Result<Self> {
+		let (width, height) = try!(check_dimensions(width, height));
+		Ok(Encoder::new(w, width, height))
+	}
+
+	/// Sets the index of the background color in the global palette.
+	///
+	/// The index is written as is, decoders reading it back via `Reader::bg_color`
+	/// obtain the same index even if the palette contains duplicate colors.
+	/// It is validated against the size of the global palette when the palette is written.
+	pub fn set_background_color(&mut self, idx: u8) -> &mut Self {
+		self.background_index = idx;
+		self
+	}
+
+	/// Sets the pixel aspect ratio byte of the logical screen descriptor.
+	///
+	/// A value of 0 (the default) means that no aspect ratio is given. Otherwise the
+	/// aspect ratio is `(value + 15) / 64`, as reported by `StreamingDecoder::aspect_ratio`.
+	pub fn set_aspect_ratio(&mut self, aspect: u8) -> &mut Self {
+		self.aspect_ratio = aspect;
+		self
+	}
+
+	/// Writes a complete image consisting of a single frame covering the logical screen
+	///
+	/// `data` contains the color indices of the image and `palette` is written as the
+	/// global palette. Returns the underlying writer once the trailer has been written.
+	pub fn encode_image(self, data: &[u8], palette: &[u8]) -> io::Result<W> {
+		let (width, height) = (self.width, self.height);
+		if data.len() != width as usize * height as usize {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"The number of pixels does not match the dimensions."
+			))
+		}
+		let mut encoder = try!(self.write_global_palette(palette));
+		try!(encoder.write_indexed_frame(width, height, data, None, &FrameOptions::default()));
+		encoder.into_inner()
+	}
+
+	/// Writes the logical screen descriptor without a global color palette
+	///
+	/// Every frame has to bring its own local palette.
+	fn write_header(mut self) -> io::Result<HeaderWritten<W>> {
+		if self.background_index != 0 {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"The background color index is not part of the global palette."
+			))
+		}
+		try!(self.write_screen_desc(0));
+		Ok(HeaderWritten {
+			enc: Some(self)
+		})
+	}
+
 	/// Writes the global color palette
+	///
+	/// The palette is written as given. Missing entries up to the next power of two
+	/// are filled with black.
 	pub fn write_global_palette(mut self, palette: &[u8]) -> io::Result<HeaderWritten<W>> {
+		try!(self.check_palette_length(palette));
 		self.global_palette = true;
 		let mut flags = 0;
 		flags |= 0b1000_0000;
 		let num_colors = palette.len() / 3;
+		if self.background_index != 0 && self.background_index as usize >= num_colors {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"The background color index is not part of the global palette."
+			))
+		}
+		self.global_colors = num_colors;
 		flags |= flag_size(num_colors);
 		flags |= flag_size(num_colors) << 4; // wtf flag
+		if self.palette_order == PaletteOrder::Sorted {
+			flags |= 0b0000_1000;
+		}
 		try!(self.write_screen_desc(flags));
 		try!(self.write_color_table(palette));
 		Ok(HeaderWritten {
-			enc: self
+			enc: Some(self)
 		})
 	}
 
@@ -198,11 +855,76 @@ impl<W: Write> Encoder<W> {
 	///
 	/// Note: This function also writes a control extension if necessary.
 	fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+		self.write_frame_parts(frame, frame.palette.as_ref().map(|p| &**p), &frame.buffer, frame.interlaced)
+	}
+
+	/// Writes an indexed image using the default frame settings
+	fn write_indexed_frame(&mut self, width: u16, height: u16, data: &[u8],
+	                       palette: Option<&[u8]>, opts: &FrameOptions) -> io::Result<()> {
+		let mut frame = Frame::default();
+		frame.width = width;
+		frame.height = height;
+		frame.delay = opts.delay;
+		frame.dispose = opts.dispose;
+		frame.transparent = opts.transparent;
+		frame.needs_user_input = opts.needs_user_input;
+		frame.top = opts.top;
+		frame.left = opts.left;
+		self.write_frame_parts(&frame, palette, data, opts.interlaced)
+	}
+
+	/// Writes a frame using the settings of `frame` but the given palette, data and interlacing.
+	///
+	/// The lines of `data` are in their natural order and reordered if `interlaced` is set.
+	fn write_frame_parts(&mut self, frame: &Frame, palette: Option<&[u8]>, data: &[u8], interlaced: bool)
+	-> io::Result<()> {
+		if frame.is_empty() {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"Frames with a width or height of zero cannot be encoded."
+			))
+		}
+		if data.len() != frame.width as usize * frame.height as usize {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"The number of pixels does not match the dimensions."
+			))
+		}
+		if frame.left as u32 + frame.width as u32 > self.width as u32
+		|| frame.top as u32 + frame.height as u32 > self.height as u32 {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"The frame exceeds the logical screen."
+			))
+		}
+		if self.palette_lock == PaletteLock::Locked {
+			try!(self.check_locked_palette(palette, data));
+		}
+		if let Some(palette) = palette {
+			try!(self.check_palette_length(palette));
+		}
+		let min_code_size = match self.min_code_size {
+			MinCodeSize::FromPalette => {
+				let num_colors = palette.map(|p| p.len() / 3).unwrap_or(self.global_colors);
+				let size = max(flag_size(num_colors) + 1, 2);
+				try!(check_min_code_size(size, data));
+				Some(size)
+			},
+			MinCodeSize::FromData => None,
+			MinCodeSize::Fixed(size) => {
+				try!(check_min_code_size(size, data));
+				Some(size)
+			}
+		};
 		// TODO commented off to pass test in lib.rs
 		//if frame.delay > 0 || frame.transparent.is_some() {
+			let dispose = match frame.dispose {
+				DisposalMethod::Any => self.default_dispose,
+				dispose => dispose
+			};
 			try!(self.write_extension(ExtensionData::new_control_ext(
 				frame.delay,
-				frame.dispose,
+				dispose,
 				frame.needs_user_input,
 				frame.transparent
 
@@ -214,11 +936,17 @@ impl<W: Write> Encoder<W> {
 		try!(self.w.write_le(frame.width));
 		try!(self.w.write_le(frame.height));
 		let mut flags = 0;
-		try!(match frame.palette {
-			Some(ref palette) => {
+		if interlaced {
+			flags |= 0b0100_0000;
+		}
+		try!(match palette {
+			Some(palette) => {
 				flags |= 0b1000_0000;
 				let num_colors = palette.len() / 3;
 				flags |= flag_size(num_colors);
+				if self.palette_order == PaletteOrder::Sorted {
+					flags |= 0b0010_0000;
+				}
 				try!(self.w.write_le(flags));
 				self.write_color_table(palette)
 			},
@@ -231,20 +959,75 @@ impl<W: Write> Encoder<W> {
 				self.w.write_le(flags)
 			}
 		});
-		self.write_image_block(&frame.buffer)
+		if interlaced {
+			self.write_image_block(&interlace(data, frame.width as usize, frame.height as usize), min_code_size)
+		} else {
+			self.write_image_block(data, min_code_size)
+		}
 	}
 
-	fn write_image_block(&mut self, data: &[u8]) -> io::Result<()> {
+	fn check_palette_length(&self, palette: &[u8]) -> io::Result<()> {
+		if self.palette_length == PaletteLength::Exact {
+			let num_colors = palette.len() / 3;
+			if palette.len() % 3 != 0 || num_colors > 256 || num_colors != 2 << flag_size(num_colors) {
+				return Err(io::Error::new(
+					io::ErrorKind::InvalidInput,
+					"The palette does not fill a complete color table."
+				))
+			}
+		}
+		Ok(())
+	}
+
+	fn check_locked_palette(&self, palette: Option<&[u8]>, data: &[u8]) -> io::Result<()> {
+		if palette.is_some() {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"Local color palettes are not allowed while the global palette is locked."
+			))
+		}
+		if data.iter().any(|&idx| idx as usize >= self.global_colors) {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"Frame references a color outside of the locked global palette."
+			))
+		}
+		Ok(())
+	}
+
+	/// Writes the compressed image data
+	///
+	/// The minimum code size is derived from `data` if it is not known beforehand.
+	fn write_image_block(&mut self, data: &[u8], min_code_size: Option<u8>) -> io::Result<()> {
 		{
-			let min_code_size: u8 = flag_size((*data.iter().max().unwrap_or(&0) as usize + 1)) + 1;
+			let min_code_size: u8 = match min_code_size {
+				Some(size) => size,
+				None => min_bit_depth(data)
+			};
+			// The GIF format does not allow code sizes smaller than 2, even for 1-bit images
+			let min_code_size = max(min_code_size, 2);
 			try!(self.w.write_le(min_code_size));
 			let mut bw = BlockWriter::new(&mut self.w);
-			let mut enc = try!(lzw::Encoder::new(lzw::LsbWriter::new(&mut bw), min_code_size));
-			try!(enc.encode_bytes(data));
+			match self.compression {
+				Compression::Lzw => {
+					let mut enc = try!(lzw::Encoder::new(lzw::LsbWriter::new(&mut bw), min_code_size));
+					try!(enc.encode_bytes(data));
+				},
+				Compression::Uncompressed => {
+					try!(bw.write_all(&encode_uncompressed(data, min_code_size)))
+				},
+				Compression::Custom(encode) => {
+					try!(bw.write_all(&encode(data, min_code_size)))
+				}
+			}
 		}
 		self.w.write_le(0u8)
 	}
 
+	/// Writes a color table as given, padded with black to the next power of two
+	///
+	/// Padding entries that are part of `table` are written as they are, such that tables
+	/// read by the decoder are reproduced byte for byte.
 	fn write_color_table(&mut self, table: &[u8]) -> io::Result<()> {
 		let num_colors = table.len() / 3;
         let size = flag_size(num_colors);
@@ -272,6 +1055,29 @@ impl<W: Write> Encoder<W> {
 		self.w.write_le(0u8)
 	}
 
+	/// Writes a looping extension with the given application identifier
+	fn write_app_loop_count(&mut self, identifier: &[u8; 11], count: u16) -> io::Result<()> {
+		self.write_app_sub_block(identifier, &[1, count as u8, (count >> 8) as u8])
+	}
+
+	/// Writes the buffering sub-block variant of a looping extension
+	fn write_app_buffer_size(&mut self, identifier: &[u8; 11], size: u32) -> io::Result<()> {
+		self.write_app_sub_block(identifier, &[
+			2, size as u8, (size >> 8) as u8, (size >> 16) as u8, (size >> 24) as u8
+		])
+	}
+
+	/// Writes an application extension consisting of the identifier and a single data sub-block
+	fn write_app_sub_block(&mut self, identifier: &[u8; 11], data: &[u8]) -> io::Result<()> {
+		try!(self.w.write_le(Block::Extension as u8));
+		try!(self.w.write_le(Extension::Application as u8));
+		try!(self.w.write_le(identifier.len() as u8));
+		try!(self.w.write_all(identifier));
+		try!(self.w.write_le(data.len() as u8));
+		try!(self.w.write_all(data));
+		self.w.write_le(0u8)
+	}
+
 	/// Writes a raw extension to the image
 	fn write_raw_extension(&mut self, func: u8, data: &[u8]) -> io::Result<()> {
 		try!(self.w.write_le(Block::Extension as u8));
@@ -283,14 +1089,39 @@ impl<W: Write> Encoder<W> {
 		self.w.write_le(0u8)
 	}
 
+	/// Writes an extension consisting of the given data sub-blocks
+	fn write_extension_sub_blocks(&mut self, func: u8, sub_blocks: &[Vec<u8>]) -> io::Result<()> {
+		if sub_blocks.iter().any(|block| block.len() == 0 || block.len() > 0xFF) {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"Extension sub-blocks must hold 1 to 255 bytes."
+			))
+		}
+		try!(self.w.write_le(Block::Extension as u8));
+		try!(self.w.write_le(func));
+		for block in sub_blocks {
+			try!(self.w.write_le(block.len() as u8));
+			try!(self.w.write_all(block));
+		}
+		self.w.write_le(0u8)
+	}
+
 	/// Writes the logical screen desriptor
+	///
+	/// Fails for a logical screen without pixels, which many decoders reject.
 	fn write_screen_desc(&mut self, flags: u8) -> io::Result<()> {
+		if self.width == 0 || self.height == 0 {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"The logical screen must have a width and height of at least 1."
+			))
+		}
 		try!(self.w.write_all(b"GIF89a"));
 		try!(self.w.write_le(self.width));
 		try!(self.w.write_le(self.height));
 		try!(self.w.write_le(flags)); // packed field
-		try!(self.w.write_le(0u8)); // bg index
-		self.w.write_le(0u8) // aspect ratio
+		try!(self.w.write_le(self.background_index)); // bg index
+		self.w.write_le(self.aspect_ratio) // aspect ratio
 	}
 }
 
@@ -307,4 +1138,746 @@ fn flag_size(size: usize) -> u8 {
         129...256 => 7,
         _ => 7
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    extern crate test;
+
+    use std::borrow::Cow;
+    use std::io;
+    use std::io::prelude::*;
+    use std::time::Duration;
+
+    use traits::{SetParameter, u16_from_le};
+    use common::{Frame, DisposalMethod, APP_ANIMEXTS, composite_frame_rgba};
+    use reader::{Decoder, ColorOutput};
+
+    use super::{Encoder, FrameOptions, AnimationPalette, encode_animation, encode_truecolor_still, PaletteOrder, PaletteLock, PaletteLength, DefaultDisposal, MinCodeSize, TrailerMode, Compression};
+
+    fn checkerboard() -> Frame<'static> {
+        let mut frame = Frame::default();
+        frame.width = 2;
+        frame.height = 2;
+        frame.buffer = Cow::Borrowed(&[0, 1, 1, 0]);
+        frame
+    }
+
+    fn encode_indexed_animation(min_code_size: MinCodeSize) -> Vec<u8> {
+        let frame: Vec<u8> = (0..100 * 100).map(|i| (i % 7) as u8 * 31).collect();
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 100, 100);
+            encoder.set(min_code_size);
+            let mut encoder = encoder.write_global_palette(&[0; 3 * 256]).unwrap();
+            for _ in 0..10 {
+                encoder.write_indexed_frame(100, 100, &frame, None, &Default::default()).unwrap();
+            }
+        }
+        data
+    }
+
+    #[bench]
+    fn bench_min_code_size_from_data(b: &mut test::Bencher) {
+        b.iter(|| test::black_box(encode_indexed_animation(MinCodeSize::FromData)));
+    }
+
+    #[bench]
+    fn bench_min_code_size_from_palette(b: &mut test::Bencher) {
+        b.iter(|| test::black_box(encode_indexed_animation(MinCodeSize::FromPalette)));
+    }
+
+    #[bench]
+    fn bench_write_borrowed_frame(b: &mut test::Bencher) {
+        let pixels: Vec<u8> = (0..500 * 500).map(|i| (i % 7) as u8 * 31).collect();
+        let mut frame = Frame::default();
+        frame.width = 500;
+        frame.height = 500;
+        frame.buffer = Cow::Borrowed(&pixels);
+        b.iter(|| {
+            let mut encoder = Encoder::new(Vec::new(), 500, 500)
+                .write_global_palette(&[0; 3 * 256]).unwrap();
+            encoder.write_frame(&frame).unwrap();
+            test::black_box(&encoder);
+        });
+        b.bytes = pixels.len() as u64
+    }
+
+    #[test]
+    fn test_write_borrowed_frame() {
+        let pixels = [0, 1, 1, 0];
+        let mut frame = checkerboard();
+        frame.buffer = Cow::Borrowed(&pixels);
+        let mut borrowed = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut borrowed, 2, 2).write_global_palette(&[0; 6]).unwrap();
+            encoder.write_frame(&frame).unwrap();
+        }
+        // The frame still refers to the original pixels
+        match frame.buffer {
+            Cow::Borrowed(buffer) => assert_eq!(buffer.as_ptr(), pixels.as_ptr()),
+            Cow::Owned(_) => panic!("the pixel buffer has been copied")
+        }
+        frame.buffer = Cow::Owned(pixels.to_vec());
+        let mut owned = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut owned, 2, 2).write_global_palette(&[0; 6]).unwrap();
+            encoder.write_frame(&frame).unwrap();
+        }
+        assert_eq!(borrowed, owned);
+    }
+
+    #[test]
+    fn test_encode_animation() {
+        let red: Vec<u8> = (0..4 * 4).flat_map(|_| vec![0xFF, 0, 0, 0xFF]).collect();
+        let blue: Vec<u8> = (0..4 * 4).flat_map(|_| vec![0, 0, 0xFF, 0xFF]).collect();
+        let frames = vec![
+            (red.clone(), Duration::from_millis(100)),
+            (blue.clone(), Duration::from_millis(250))
+        ];
+        for &palette in &[AnimationPalette::PerFrame, AnimationPalette::Shared] {
+            let mut data = Vec::new();
+            encode_animation(&mut data, 4, 4, &frames, 0, palette).unwrap();
+            assert!(encode_animation(Vec::new(), 4, 5, &frames, 0, palette).is_err());
+            // Only the shared palette is written as global color table
+            assert_eq!(data[10] & 0x80 != 0, palette == AnimationPalette::Shared);
+
+            let mut decoder = Decoder::new(&*data);
+            decoder.set(ColorOutput::RGBA);
+            let mut decoder = decoder.read_info().unwrap();
+            let mut decoded = Vec::new();
+            while let Some(frame) = decoder.read_next_frame().unwrap() {
+                assert_eq!(frame.palette.is_none(), palette == AnimationPalette::Shared);
+                decoded.push((frame.buffer.to_vec(), frame.delay));
+            }
+            assert_eq!(decoded, vec![(red.clone(), 10), (blue.clone(), 25)]);
+            assert_eq!(decoder.loop_count(), Some(0));
+        }
+    }
+
+    #[test]
+    fn test_fixed_min_code_size() {
+        let indices = [0, 1, 2, 3, 3, 2, 1, 0, 1];
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 3, 3);
+            encoder.set(MinCodeSize::Fixed(8));
+            let mut encoder = encoder.write_global_palette(&[0; 12]).unwrap();
+            encoder.write_indexed_frame(3, 3, &indices, None, &Default::default()).unwrap();
+        }
+        let pos = data.iter().position(|&b| b == 0x2C).unwrap();
+        assert_eq!(data[pos + 10], 8);
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        assert_eq!(&*decoder.read_next_frame().unwrap().unwrap().buffer, &indices[..]);
+
+        for &size in &[1, 9] {
+            let mut encoder = Encoder::new(Vec::new(), 3, 3);
+            encoder.set(MinCodeSize::Fixed(size));
+            let mut encoder = encoder.write_global_palette(&[0; 12]).unwrap();
+            assert!(encoder.write_indexed_frame(3, 3, &indices, None, &Default::default()).is_err());
+        }
+        let mut encoder = Encoder::new(Vec::new(), 3, 3);
+        encoder.set(MinCodeSize::Fixed(2));
+        let mut encoder = encoder.write_global_palette(&[0; 24]).unwrap();
+        assert!(encoder.write_indexed_frame(3, 3, &[4; 9], None, &Default::default()).is_err());
+    }
+
+    #[test]
+    fn test_palette_min_code_size_out_of_range() {
+        let mut encoder = Encoder::new(Vec::new(), 3, 1);
+        encoder.set(MinCodeSize::FromPalette);
+        let mut encoder = encoder.write_global_palette(&[0; 12]).unwrap();
+        // A code size of 2 covers the four colors of the global palette
+        encoder.write_indexed_frame(3, 1, &[0, 1, 3], None, &Default::default()).unwrap();
+        let err = encoder.write_indexed_frame(3, 1, &[0, 1, 7], None, &Default::default()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = encoder.write_indexed_frame(3, 1, &[0, 9, 1], Some(&[0; 6]), &Default::default()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_sorted_palette() {
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 2, 2);
+            encoder.set(PaletteOrder::Sorted);
+            let mut encoder = encoder.write_global_palette(&[0, 0, 0, 0xFF, 0xFF, 0xFF]).unwrap();
+            encoder.write_frame(&checkerboard()).unwrap();
+        }
+        let decoder = Decoder::new(&*data).read_info().unwrap();
+        assert!(decoder.global_palette_sorted());
+    }
+
+    #[test]
+    fn test_exact_palette_length() {
+        // Five colors, the remaining three entries of the color table are not black, as
+        // some encoders leave them
+        let colors = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 4, 2).write_global_palette(&colors).unwrap();
+            encoder.write_indexed_frame(4, 2, &[0, 1, 2, 3, 4, 3, 2, 1], None, &Default::default()).unwrap();
+        }
+        assert_eq!(data[10] & 0b111, 2);
+        assert_eq!(&data[28..37], &[0; 9][..]);
+        for (i, b) in data[28..37].iter_mut().enumerate() {
+            *b = 0xF0 + i as u8
+        }
+
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        let palette = decoder.global_palette().unwrap().to_vec();
+        assert_eq!(palette.len(), 24);
+        let mut data2 = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data2, decoder.width(), decoder.height());
+            encoder.set(PaletteLength::Exact);
+            let mut encoder = encoder.write_global_palette(&palette).unwrap();
+            while let Some(frame) = decoder.read_next_frame().unwrap() {
+                encoder.write_frame(frame).unwrap();
+            }
+        }
+        // The color table, including its unused entries, is reproduced exactly
+        assert_eq!(data2, data);
+
+        // The five colors alone do not fill a color table
+        let mut encoder = Encoder::new(Vec::new(), 4, 2);
+        encoder.set(PaletteLength::Exact);
+        assert!(encoder.write_global_palette(&colors).is_err());
+        let mut encoder = Encoder::new(Vec::new(), 4, 2);
+        encoder.set(PaletteLength::Exact);
+        let mut encoder = encoder.write_global_palette(&palette).unwrap();
+        let mut frame = checkerboard();
+        frame.palette = Some(colors.to_vec());
+        assert!(encoder.write_frame(&frame).is_err());
+        frame.palette = Some(colors[..12].to_vec());
+        encoder.write_frame(&frame).unwrap();
+    }
+
+    #[test]
+    fn test_locked_palette() {
+        let mut data = Vec::new();
+        let mut encoder = Encoder::new(&mut data, 2, 2);
+        encoder.set(PaletteLock::Locked);
+        let mut encoder = encoder.write_global_palette(&[0, 0, 0, 0xFF, 0xFF, 0xFF]).unwrap();
+        encoder.write_frame(&checkerboard()).unwrap();
+        let mut frame = checkerboard();
+        frame.buffer = Cow::Borrowed(&[0, 1, 2, 0]);
+        assert!(encoder.write_frame(&frame).is_err());
+        let mut frame = checkerboard();
+        frame.palette = Some(vec![0, 0, 0, 0xFF, 0xFF, 0xFF]);
+        assert!(encoder.write_frame(&frame).is_err());
+    }
+
+    #[test]
+    fn test_truecolor_still() {
+        // 1024 distinct colors and a transparent corner
+        let mut pixels: Vec<u8> = (0..32 * 32).flat_map(|i| {
+            vec![(i % 32 * 8) as u8, (i / 32 * 8) as u8, 0x80, 0xFF]
+        }).collect();
+        for pix in pixels[..4 * 3].iter_mut() {
+            *pix = 0;
+        }
+        let mut data = Vec::new();
+        encode_truecolor_still(&mut data, 32, 32, &pixels).unwrap();
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(ColorOutput::RGBA);
+        let mut decoder = decoder.read_info().unwrap();
+        let mut canvas = vec![0; 32 * 32 * 4];
+        let mut frames = 0;
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            assert_eq!(frame.delay, 0);
+            assert!(frame.width as usize * frame.height as usize >= 16 * 16);
+            composite_frame_rgba(&mut canvas, 32, &frame.buffer, frame);
+            frames += 1;
+        }
+        assert!(frames >= 4);
+        // The colors are reproduced exactly
+        assert_eq!(canvas, pixels);
+        assert!(encode_truecolor_still(Vec::new(), 32, 32, &pixels[4..]).is_err());
+    }
+
+    #[test]
+    fn test_palette_padding_round_trip() {
+        // Only the first two colors are used, the remaining entries are non-zero padding
+        let global = [0, 0, 0, 0xFF, 0xFF, 0xFF, 0xAB, 0xCD, 0xEF, 1, 2, 3];
+        let local = [9, 9, 9, 8, 8, 8, 7, 7, 7, 6, 6, 6, 5, 5, 5, 4, 4, 4, 3, 3, 3, 2, 2, 2];
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 2, 2).write_global_palette(&global).unwrap();
+            encoder.write_frame(&checkerboard()).unwrap();
+            let mut frame = checkerboard();
+            frame.palette = Some(local.to_vec());
+            encoder.write_frame(&frame).unwrap();
+        }
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(ColorOutput::Indexed);
+        let mut decoder = decoder.read_info().unwrap();
+        let mut data2 = Vec::new();
+        {
+            let palette = decoder.global_palette().unwrap().to_vec();
+            assert_eq!(palette, global);
+            let mut encoder = Encoder::new(&mut data2, 2, 2).write_global_palette(&palette).unwrap();
+            while let Some(frame) = decoder.read_next_frame().unwrap() {
+                encoder.write_frame(frame).unwrap();
+            }
+        }
+        assert_eq!(data2, data);
+        // The padding of the local color table is preserved as well
+        assert!(data2.windows(local.len()).any(|table| table == &local[..]));
+    }
+
+    #[test]
+    fn test_global_palette_animation() {
+        let palette = [0, 0, 0, 0xFF, 0xFF, 0xFF];
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 2, 2).write_global_palette(&palette).unwrap();
+            let mut frame = checkerboard();
+            encoder.write_frame(&frame).unwrap();
+            frame.buffer = Cow::Borrowed(&[1, 0, 0, 1]);
+            encoder.write_frame(&frame).unwrap();
+        }
+        // Neither image descriptor has a local color table or any other flag set
+        let descriptors: Vec<usize> = (0..data.len())
+            .filter(|&i| data[i] == 0x2C && data[i + 1..i + 5] == [0, 0, 0, 0])
+            .collect();
+        assert_eq!(descriptors.len(), 2);
+        for &i in &descriptors {
+            assert_eq!(data[i + 9], 0);
+        }
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        let mut buffers = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            assert!(frame.palette.is_none());
+            buffers.push(frame.buffer.to_vec());
+        }
+        assert!(!decoder.current_frame_has_local_palette());
+        assert_eq!(buffers, vec![vec![0, 1, 1, 0], vec![1, 0, 0, 1]]);
+        assert_eq!(decoder.palette().unwrap(), &palette[..]);
+    }
+
+    #[test]
+    fn test_raw_extension_sub_blocks() {
+        for &(len, blocks) in &[(0, &[][..]), (255, &[255][..]), (256, &[255, 1][..]), (600, &[255, 255, 90][..])] {
+            let payload: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let mut data = Vec::new();
+            {
+                let mut encoder = Encoder::new(&mut data, 1, 1).write_global_palette(&[0; 6]).unwrap();
+                encoder.write_raw_extension(0xFE, &payload).unwrap();
+            }
+            // Header and global color table, followed by the extension and the trailer
+            let mut ext = &data[13 + 6..];
+            assert_eq!(&ext[..2], &[0x21, 0xFE]);
+            ext = &ext[2..];
+            let mut content = Vec::new();
+            for &block in blocks {
+                assert_eq!(ext[0], block);
+                content.extend(ext[1..1 + block as usize].iter().cloned());
+                ext = &ext[1 + block as usize..];
+            }
+            assert_eq!(ext, &[0, 0x3B]);
+            assert_eq!(content, payload);
+        }
+    }
+
+    #[test]
+    fn test_zero_dimensions() {
+        for &(width, height) in &[(0, 1), (1, 0), (0, 0)] {
+            let mut data = Vec::new();
+            assert!(Encoder::new(&mut data, width, height).write_global_palette(&[0; 6]).is_err());
+            // Nothing has been written
+            assert!(data.is_empty());
+            assert!(Encoder::new(Vec::new(), width, height).encode_image(&[], &[0; 6]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_interlaced_frame() {
+        let mut frame = Frame::default();
+        frame.width = 3;
+        frame.height = 9;
+        frame.interlaced = true;
+        frame.buffer = Cow::Owned((0..27).map(|i| (i / 3) as u8).collect());
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 3, 9).write_global_palette(&[0; 3 * 16]).unwrap();
+            encoder.write_frame(&frame).unwrap();
+        }
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(ColorOutput::Indexed);
+        let mut decoder = decoder.read_info().unwrap();
+        decoder.next_frame().unwrap().unwrap();
+        // The lines are stored in the order of the interlacing passes
+        let mut stored = vec![0; 27];
+        decoder.fill_buffer(&mut stored).unwrap();
+        let lines: Vec<u8> = stored.chunks(3).map(|line| line[0]).collect();
+        assert_eq!(lines, vec![0, 8, 4, 2, 6, 1, 3, 5, 7]);
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        let decoded = decoder.read_next_frame().unwrap().unwrap();
+        assert!(decoded.interlaced);
+        assert_eq!(decoded.buffer, frame.buffer);
+    }
+
+    #[test]
+    fn test_best_interlacing() {
+        // Alternating lines favor the interlaced order, the other image is noise
+        let mut seed = 7u32;
+        let images: Vec<Vec<u8>> = vec![
+            (0..32 * 32).map(|i| if i / 32 % 2 == 0 { (i % 5) as u8 } else { (i % 3 + 5) as u8 }).collect(),
+            (0..32 * 32).map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                (seed >> 16) as u8 % 16
+            }).collect()
+        ];
+        for image in images {
+            let mut frame = Frame::default();
+            frame.width = 32;
+            frame.height = 32;
+            frame.buffer = Cow::Owned(image);
+            let mut data = Vec::new();
+            let (interlaced, sizes) = {
+                let mut encoder = Encoder::new(&mut data, 32, 32).write_global_palette(&[0; 3 * 16]).unwrap();
+                let plain_size = encoder.frame_encoded_size(&frame).unwrap();
+                frame.interlaced = true;
+                let interlaced_size = encoder.frame_encoded_size(&frame).unwrap();
+                frame.interlaced = false;
+                (encoder.write_frame_best_interlacing(&frame).unwrap(), (plain_size, interlaced_size))
+            };
+            let expected_size = if interlaced { sizes.1 } else { sizes.0 };
+            assert_eq!(expected_size, ::std::cmp::min(sizes.0, sizes.1));
+            // Header, global color table and trailer
+            assert_eq!(data.len(), 13 + 3 * 16 + expected_size + 1);
+            let mut decoder = Decoder::new(&*data).read_info().unwrap();
+            let decoded = decoder.read_next_frame().unwrap().unwrap();
+            assert_eq!(decoded.interlaced, interlaced);
+            assert_eq!(decoded.buffer, frame.buffer);
+        }
+    }
+
+    fn encode_with(compression: Compression, width: u16, height: u16, indices: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, width, height);
+            encoder.set(compression);
+            let mut encoder = encoder.write_global_palette(&[0; 3 * 256]).unwrap();
+            encoder.write_indexed_frame(width, height, indices, None, &Default::default()).unwrap();
+        }
+        data
+    }
+
+    fn lzw_encode(data: &[u8], min_code_size: u8) -> Vec<u8> {
+        let mut result = Vec::new();
+        ::lzw::encode(data, ::lzw::LsbWriter::new(&mut result), min_code_size).unwrap();
+        result
+    }
+
+    #[test]
+    fn test_encode_image() {
+        let palette = &[0, 0, 0, 0xFF, 0, 0, 0, 0xFF, 0];
+        let indices = [0, 1, 2, 2, 1, 0];
+        let data = Encoder::new(Vec::new(), 3, 2).encode_image(&indices, palette).unwrap();
+        let mut expected = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut expected, 3, 2).write_global_palette(palette).unwrap();
+            encoder.write_indexed_frame(3, 2, &indices, None, &Default::default()).unwrap();
+        }
+        assert_eq!(data, expected);
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        assert_eq!(&decoder.global_palette().unwrap()[..9], palette);
+        {
+            let frame = decoder.read_next_frame().unwrap().unwrap();
+            assert_eq!((frame.width, frame.height), (3, 2));
+            assert_eq!(&*frame.buffer, &indices);
+        }
+        assert!(decoder.read_next_frame().unwrap().is_none());
+        assert!(Encoder::new(Vec::new(), 3, 2).encode_image(&indices[..5], palette).is_err());
+    }
+
+    #[test]
+    fn test_compression() {
+        for &(max, len) in &[(1, 7), (3, 100), (15, 1000), (255, 10000)] {
+            let indices: Vec<u8> = (0..len).map(|i| (i * 7 / 3 % (max + 1)) as u8).collect();
+            let compressed = encode_with(Compression::Lzw, len as u16, 1, &indices);
+            for &compression in &[Compression::Uncompressed, Compression::Custom(lzw_encode)] {
+                let data = encode_with(compression, len as u16, 1, &indices);
+                let mut decoder = Decoder::new(&*data).read_info().unwrap();
+                assert_eq!(&*decoder.read_next_frame().unwrap().unwrap().buffer, &*indices);
+                if let Compression::Uncompressed = compression {
+                    assert!(data.len() > compressed.len());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_frame_encoded_size() {
+        let mut data = Vec::new();
+        let mut encoder = Encoder::new(&mut data, 2, 2).write_global_palette(&[0; 6]).unwrap();
+        let mut frame = checkerboard();
+        let mut sizes = Vec::new();
+        sizes.push(encoder.frame_encoded_size(&frame).unwrap());
+        encoder.write_frame(&frame).unwrap();
+        frame.palette = Some(vec![0; 3 * 16]);
+        frame.buffer = Cow::Owned(vec![3, 15, 0, 7]);
+        sizes.push(encoder.frame_encoded_size(&frame).unwrap());
+        encoder.write_frame(&frame).unwrap();
+        frame.left = 1;
+        assert!(encoder.frame_encoded_size(&frame).is_err());
+        let written = encoder.into_inner().unwrap().len();
+        // Header (6), screen descriptor (7), global palette (6) and trailer (1)
+        assert_eq!(sizes.iter().sum::<usize>(), written - 20);
+        // The local palette is part of the size
+        assert!(sizes[1] > sizes[0] + 3 * 16);
+    }
+
+    #[test]
+    fn test_delay_round_trip() {
+        let delays = [0, 1, 255, 256, 65535];
+        let encode = |frames: &[Frame]| {
+            let mut data = Vec::new();
+            {
+                let mut encoder = Encoder::new(&mut data, 2, 2).write_global_palette(&[0; 6]).unwrap();
+                for frame in frames {
+                    encoder.write_frame(frame).unwrap();
+                }
+            }
+            data
+        };
+        let frames: Vec<Frame> = delays.iter().map(|&delay| {
+            let mut frame = checkerboard();
+            frame.delay = delay;
+            frame
+        }).collect();
+        let data = encode(&frames);
+        // Control extensions, the delay is stored little endian in bytes 4 and 5
+        let stored: Vec<u16> = data.windows(3).enumerate()
+            .filter(|&(_, w)| w == [0x21, 0xF9, 0x04])
+            .map(|(i, _)| u16_from_le([data[i + 4], data[i + 5]]))
+            .collect();
+        assert_eq!(stored, delays);
+
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        let mut decoded = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            decoded.push(frame.clone());
+        }
+        assert_eq!(decoded.iter().map(|f| f.delay).collect::<Vec<_>>(), delays);
+        assert_eq!(encode(&decoded), data);
+    }
+
+    #[test]
+    fn test_transparent_control_ext() {
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 2, 2).write_global_palette(&[0; 6]).unwrap();
+            let mut frame = checkerboard();
+            frame.transparent = Some(1);
+            frame.dispose = DisposalMethod::Any;
+            encoder.write_frame(&frame).unwrap();
+        }
+        // Header (6), screen descriptor (7) and global palette (6)
+        let ext = &data[19..27];
+        // Introducer, label, block size, flags (transparency only), delay, index, terminator
+        assert_eq!(ext, &[0x21, 0xF9, 0x04, 0b0000_0001, 0, 0, 1, 0]);
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        let frame = decoder.read_next_frame().unwrap().unwrap();
+        assert_eq!(frame.transparent, Some(1));
+        assert_eq!(frame.delay, 0);
+        assert_eq!(frame.dispose, DisposalMethod::Any);
+        assert!(!frame.needs_user_input);
+    }
+
+    #[test]
+    fn test_default_disposal() {
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 2, 2);
+            encoder.set(DefaultDisposal(DisposalMethod::Background));
+            let mut encoder = encoder.write_global_palette(&[0, 0, 0, 0xFF, 0xFF, 0xFF]).unwrap();
+            let mut frame = checkerboard();
+            frame.dispose = DisposalMethod::Any;
+            encoder.write_frame(&frame).unwrap();
+            frame.dispose = DisposalMethod::Keep;
+            encoder.write_frame(&frame).unwrap();
+        }
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        assert_eq!(decoder.read_next_frame().unwrap().unwrap().dispose, DisposalMethod::Background);
+        assert_eq!(decoder.read_next_frame().unwrap().unwrap().dispose, DisposalMethod::Keep);
+    }
+
+    #[test]
+    fn test_background_color() {
+        let palette = &[0, 0, 0, 0, 0, 0, 0xFF, 0xFF, 0xFF];
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 2, 2);
+            encoder.set_background_color(1);
+            let mut encoder = encoder.write_global_palette(palette).unwrap();
+            encoder.write_frame(&checkerboard()).unwrap();
+        }
+        let decoder = Decoder::new(&*data).read_info().unwrap();
+        assert_eq!(decoder.bg_color(), 1);
+        let mut encoder = Encoder::new(Vec::new(), 2, 2);
+        encoder.set_background_color(3);
+        assert!(encoder.write_global_palette(palette).is_err());
+    }
+
+    #[test]
+    fn test_write_indexed_frame() {
+        let palette = &[0, 0, 0, 0xFF, 0xFF, 0xFF];
+        let mut expected = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut expected, 2, 2).write_global_palette(&[]).unwrap();
+            let mut frame = checkerboard();
+            frame.palette = Some(palette.to_vec());
+            encoder.write_frame(&frame).unwrap();
+        }
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 2, 2).write_global_palette(&[]).unwrap();
+            encoder.write_indexed_frame(2, 2, &[0, 1, 1, 0], Some(palette), &Default::default()).unwrap();
+            assert!(encoder.write_indexed_frame(2, 2, &[0, 1, 1], Some(palette), &Default::default()).is_err());
+            assert!(encoder.write_indexed_frame(2, 2, &[0; 5], Some(palette), &Default::default()).is_err());
+        }
+        assert_eq!(data, expected);
+
+        let mut frame = checkerboard();
+        frame.palette = Some(palette.to_vec());
+        frame.delay = 7;
+        frame.dispose = DisposalMethod::Background;
+        frame.transparent = Some(1);
+        frame.left = 1;
+        frame.top = 2;
+        frame.interlaced = true;
+        let mut expected = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut expected, 4, 4).write_global_palette(&[]).unwrap();
+            encoder.write_frame(&frame).unwrap();
+        }
+        let opts = FrameOptions {
+            delay: 7,
+            dispose: DisposalMethod::Background,
+            transparent: Some(1),
+            left: 1,
+            top: 2,
+            interlaced: true,
+            .. FrameOptions::default()
+        };
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 4, 4).write_global_palette(&[]).unwrap();
+            encoder.write_indexed_frame(2, 2, &[0, 1, 1, 0], Some(palette), &opts).unwrap();
+        }
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn test_write_packed_frame() {
+        let indices = [0, 1, 2, 3, 3, 2, 1, 0, 1, 1];
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 5, 2).write_global_palette(&[0; 12]).unwrap();
+            encoder.write_packed_frame(
+                5, 2, &[0b00_01_10_11, 0b11_000000, 0b10_01_00_01, 0b01_000000], 2, None,
+                &Default::default()
+            ).unwrap();
+            assert!(encoder.write_packed_frame(5, 2, &[0; 3], 2, None, &Default::default()).is_err());
+            assert!(encoder.write_packed_frame(5, 2, &[0; 5], 2, None, &Default::default()).is_err());
+            assert!(encoder.write_packed_frame(5, 2, &[0; 8], 3, None, &Default::default()).is_err());
+        }
+        let mut decoder = ::Decoder::new(&*data).read_info().unwrap();
+        assert_eq!(&*decoder.read_next_frame().unwrap().unwrap().buffer, &indices[..]);
+    }
+
+    #[test]
+    fn test_frame_bounds() {
+        let mut encoder = Encoder::new(Vec::new(), 4, 4).write_global_palette(&[0; 12]).unwrap();
+        let mut frame = checkerboard();
+        frame.left = 2;
+        frame.top = 2;
+        encoder.write_frame(&frame).unwrap();
+        frame.left = 3;
+        assert!(encoder.write_frame(&frame).is_err());
+        frame.left = 0xFFFF;
+        assert!(encoder.write_frame(&frame).is_err());
+        frame.left = 0;
+        frame.top = 3;
+        assert!(encoder.write_frame(&frame).is_err());
+    }
+
+    #[test]
+    fn test_zero_area_frame() {
+        let mut encoder = Encoder::new(Vec::new(), 2, 2).write_global_palette(&[0; 12]).unwrap();
+        let mut frame = checkerboard();
+        frame.width = 0;
+        assert!(encoder.write_frame(&frame).is_err());
+        assert!(encoder.write_indexed_frame(2, 0, &[], None, &Default::default()).is_err());
+    }
+
+    #[test]
+    fn test_animexts_loop_count() {
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 2, 2).write_global_palette(&[0; 12]).unwrap();
+            encoder.write_app_loop_count(APP_ANIMEXTS, 5).unwrap();
+            encoder.write_app_buffer_size(APP_ANIMEXTS, 1024).unwrap();
+            encoder.write_frame(&checkerboard()).unwrap();
+        }
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        decoder.next_frame().unwrap().unwrap();
+        assert_eq!(decoder.loop_count(), Some(5));
+    }
+
+    #[test]
+    fn test_two_color_round_trip() {
+        let checkerboard: Vec<u8> = (0..16).map(|i| ((i + i / 4) % 2) as u8).collect();
+        let mut data = Vec::new();
+        {
+            let encoder = Encoder::new(&mut data, 4, 4);
+            let mut encoder = encoder.write_global_palette(&[0, 0, 0, 0xFF, 0xFF, 0xFF]).unwrap();
+            encoder.write_indexed_frame(4, 4, &checkerboard, None, &Default::default()).unwrap();
+        }
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        assert_eq!(decoder.global_palette().unwrap(), &[0, 0, 0, 0xFF, 0xFF, 0xFF][..]);
+        assert_eq!(&*decoder.read_next_frame().unwrap().unwrap().buffer, &*checkerboard);
+    }
+
+    #[test]
+    fn test_into_inner() {
+        let encoder = Encoder::new(Vec::new(), 2, 2);
+        let mut encoder = encoder.write_global_palette(&[0; 12]).unwrap();
+        encoder.write_indexed_frame(2, 2, &[0, 1, 2, 3], None, &Default::default()).unwrap();
+        let data = encoder.into_inner().unwrap();
+        assert_eq!(&data[data.len() - 2..], &[0, 0x3B]);
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        assert_eq!(&*decoder.read_next_frame().unwrap().unwrap().buffer, &[0, 1, 2, 3]);
+        assert!(decoder.read_next_frame().unwrap().is_none());
+
+        // The trailer is not written twice
+        let encoder = Encoder::new(Vec::new(), 2, 2);
+        let mut encoder = encoder.write_global_palette(&[0; 12]).unwrap();
+        encoder.write_trailer().unwrap();
+        let data = encoder.into_inner().unwrap();
+        assert_eq!(data.iter().filter(|&&b| b == 0x3B).count(), 1);
+    }
+
+    #[test]
+    fn test_suppressed_trailer() {
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 2, 2);
+            encoder.set(TrailerMode::Suppressed);
+            let mut encoder = encoder.write_global_palette(&[0; 12]).unwrap();
+            encoder.write_indexed_frame(2, 2, &[0, 1, 2, 3], None, &Default::default()).unwrap();
+        }
+        // The image data ends with the block terminator
+        assert_eq!(data.last(), Some(&0));
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 2, 2).write_global_palette(&[0; 12]).unwrap();
+            encoder.write_indexed_frame(2, 2, &[0, 1, 2, 3], None, &Default::default()).unwrap();
+            encoder.write_trailer().unwrap();
+        }
+        assert_eq!(&data[data.len() - 2..], &[0, 0x3B]);
+    }
+}
@@ -38,14 +38,18 @@
 //! 
 //! let color_map = &[0, 0, 0, 0xFF, 0xFF, 0xFF];
 //! let mut frame = Frame::default();
+//! frame.width = 10;
+//! frame.height = 10;
 //! let mut buffer = Vec::new();
 //! // Generate checkerboard lattice
-//! for (i, j) in (0..10).zip(0..10) {
-//! 	buffer.push(if (i * j) % 2 == 0 {
-//! 		1
-//! 	} else {
-//! 		0
-//! 	})
+//! for i in 0..10 {
+//! 	for j in 0..10 {
+//! 		buffer.push(if (i + j) % 2 == 0 {
+//! 			1
+//! 		} else {
+//! 			0
+//! 		})
+//! 	}
 //! }
 //! frame.buffer = Cow::Owned(buffer);
 //! let mut image = Vec::new();
@@ -111,6 +115,7 @@ extern crate lzw;
 
 mod traits;
 mod common;
+mod text;
 mod util;
 mod reader;
 mod encoder;
@@ -121,32 +126,62 @@ mod c_api_utils;
 pub mod c_api;
 
 pub use traits::{SetParameter, Parameter};
-pub use common::{Block, Extension, DisposalMethod, Frame};
+pub use common::{Block, Extension, DisposalMethod, Frame, Palette, Colors, EnumeratePixels};
+pub use common::{APP_NETSCAPE, APP_ANIMEXTS, MAX_DIMENSION, retime, merge_duplicate_frames, promote_global_palette, diff_encode};
+pub use common::{quantize_frames, composite_frame_rgba, composite_frame_rgba_with, TransparentPixels, PaletteBuilder};
+pub use text::PlainText;
 
-pub use reader::{StreamingDecoder, Decoded, DecodingError};
+pub use reader::{StreamingDecoder, Decoded, DecodingError, inspect, BlockInfo};
 /// StreamingDecoder configuration parameters
-pub use reader::{ColorOutput, Extensions};
-pub use reader::{Reader, Decoder};
+pub use reader::{ColorOutput, Extensions, FrameBounds, OutputLimit, ExtensionLimit, SignatureScan, DisposalCheck, ImageData, Header};
+pub use reader::{Reader, Decoder, GifMetadata, FrameExtraction, MaxFrames, decode_first_frame_rgba};
 
-pub use encoder::{Encoder, HeaderWritten, ExtensionData};
+pub use encoder::{Encoder, HeaderWritten, FrameOptions, ExtensionData, PaletteOrder, PaletteLock, PaletteLength, DefaultDisposal, MinCodeSize, TrailerMode, Compression};
+pub use encoder::{encode_animation, encode_truecolor_still, AnimationPalette};
 
 #[cfg(test)]
-#[test]
-fn round_trip() {
-	use std::io::prelude::*;
-	use std::fs::File;
-	let mut data = vec![];
-	File::open("tests/samples/sample_1.gif").unwrap().read_to_end(&mut data).unwrap();
-	let mut decoder = Decoder::new(&*data).read_info().unwrap();
+fn transcode(data: &[u8]) -> Vec<u8> {
+	let mut decoder = Decoder::new(data).read_info().unwrap();
 	let palette: Vec<u8> = decoder.palette().unwrap().into();
+	let metadata = decoder.metadata();
 	let frame = decoder.read_next_frame().unwrap().unwrap();
 	let mut data2 = vec![];
 	{
     	let encoder = {
-    		Encoder::new(&mut data2, frame.width, frame.height)
+    		let mut encoder = Encoder::new(&mut data2, frame.width, frame.height);
+    		encoder.set_background_color(metadata.background_index);
+    		encoder.set_aspect_ratio(metadata.aspect);
+    		encoder
     	};
 		let mut encoder = encoder.write_global_palette(&palette).unwrap();		
 		encoder.write_frame(frame).unwrap();
 	}
-	assert_eq!(&data[..], &data2[..])
+	data2
+}
+
+#[cfg(test)]
+#[test]
+fn round_trip() {
+	use std::io::prelude::*;
+	use std::fs::File;
+	let mut data = vec![];
+	File::open("tests/samples/sample_1.gif").unwrap().read_to_end(&mut data).unwrap();
+	assert_eq!(&data[..], &transcode(&data)[..])
+}
+
+#[cfg(test)]
+#[test]
+fn round_trip_background_and_aspect_ratio() {
+	use std::io::prelude::*;
+	use std::fs::File;
+	let mut data = vec![];
+	File::open("tests/samples/sample_1.gif").unwrap().read_to_end(&mut data).unwrap();
+	// Background index and pixel aspect ratio of the logical screen descriptor
+	data[11] = 2;
+	data[12] = 49;
+	let data2 = transcode(&data);
+	assert_eq!(&data[..], &data2[..]);
+	let metadata = Decoder::new(&*data2).read_info().unwrap().metadata();
+	assert_eq!(metadata.background_index, 2);
+	assert_eq!(metadata.aspect, 49);
 }
\ No newline at end of file
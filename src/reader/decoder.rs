@@ -1,5 +1,6 @@
 use std::cmp;
 use std::mem;
+use std::ops::Range;
 use std::default::Default;
 use std::rc::Rc;
 
@@ -8,12 +9,16 @@ use std::io::prelude::*;
 
 use lzw;
 
-use traits::{SetParameter, Parameter};
-use common::{Frame, Block, Extension, DisposalMethod};
+use traits::{SetParameter, Parameter, ReadBytesExt, u16_from_le};
+use common::{Frame, Block, Extension, DisposalMethod, APP_NETSCAPE, APP_ANIMEXTS};
+use text::PlainText;
 
 /// GIF palettes are RGB
 pub const PLTE_CHANNELS: usize = 3;
 
+/// Largest minimum code size the GIF format allows, one more would exceed 8 bit indices
+const MAX_MIN_CODE_SIZE: u8 = 8;
+
 #[derive(Debug)]
 /// Decoding error.
 pub enum DecodingError {
@@ -21,6 +26,23 @@ pub enum DecodingError {
     Format(&'static str),
     /// Internal (logic) error.
     Internal(&'static str),
+    /// Returned if the signature is `GIF` followed by a version other than `87a` or `89a`.
+    ///
+    /// Contains the version found. Input that does not start with `GIF` at all is
+    /// reported as `Format("not a GIF file")`.
+    UnsupportedVersion([u8; 3]),
+    /// Returned if the LZW compressed image data is malformed.
+    ///
+    /// Contains the index of the offending data sub-block and the approximate
+    /// offset within the compressed image data of the current frame.
+    Lzw {
+        /// Index of the sub-block in which the error occured.
+        sub_block: usize,
+        /// Number of compressed bytes of the frame that had been decoded successfully.
+        offset: usize,
+        /// Error reported by the LZW decoder.
+        error: io::Error,
+    },
     /// Wraps `std::io::Error`.
     Io(io::Error),
 }
@@ -51,6 +73,100 @@ impl Parameter<StreamingDecoder> for Extensions {
     }
 }
 
+/// Handling of the reserved disposal values 4 to 7 in control extensions
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum DisposalCheck {
+    /// Reserved values are treated as `DisposalMethod::Any`, like most viewers do.
+    Lenient,
+    /// Reserved values are reported as a format error.
+    Strict,
+}
+
+impl Parameter<StreamingDecoder> for DisposalCheck {
+    fn set_param(self, this: &mut StreamingDecoder) {
+        this.disposal_check = self
+    }
+}
+
+/// Configures whether the image data is decompressed
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum ImageData {
+    /// The image data is decompressed.
+    Decode,
+    /// The image data is skipped without decompressing it, no `Decoded::Data` is emitted.
+    ///
+    /// Frames are still reported with their control information. Useful to read the
+    /// metadata, extensions and timing of an animation quickly.
+    Skip,
+}
+
+impl Parameter<StreamingDecoder> for ImageData {
+    fn set_param(self, this: &mut StreamingDecoder) {
+        this.image_data = self
+    }
+}
+
+/// Limits the amount of decoded image data
+///
+/// Protects against decompression bombs, i.e. tiny LZW data expanding to a huge output.
+/// A format error is returned if a limit is exceeded. By default no limits are imposed.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct OutputLimit {
+    /// Maximum number of decoded bytes per frame.
+    pub per_frame: usize,
+    /// Maximum number of decoded bytes in the whole file.
+    pub total: usize,
+}
+
+impl Default for OutputLimit {
+    fn default() -> OutputLimit {
+        OutputLimit {
+            per_frame: usize::max_value(),
+            total: usize::max_value(),
+        }
+    }
+}
+
+impl Parameter<StreamingDecoder> for OutputLimit {
+    fn set_param(self, this: &mut StreamingDecoder) {
+        this.output_limit = self
+    }
+}
+
+/// Number of leading bytes skipped while searching for the GIF signature
+///
+/// Some files start with stray bytes, e.g. a byte order mark, before the signature.
+/// Defaults to 0, which requires the stream to start with the signature. The scan stops
+/// at the first "GIF", unsupported versions are reported as usual. The offsets reported
+/// by the decoder include the skipped bytes.
+#[derive(PartialEq, Debug, Copy, Clone, Default)]
+pub struct SignatureScan(pub usize);
+
+impl Parameter<StreamingDecoder> for SignatureScan {
+    fn set_param(self, this: &mut StreamingDecoder) {
+        this.signature_scan = self
+    }
+}
+
+/// Limits the size of the content of a single extension
+///
+/// The content of an extension is buffered until the extension is complete. A format
+/// error is returned if it exceeds the limit, which defaults to 16 MiB.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct ExtensionLimit(pub usize);
+
+impl Default for ExtensionLimit {
+    fn default() -> ExtensionLimit {
+        ExtensionLimit(1 << 24)
+    }
+}
+
+impl Parameter<StreamingDecoder> for ExtensionLimit {
+    fn set_param(self, this: &mut StreamingDecoder) {
+        this.extension_limit = self
+    }
+}
+
 /// Indicates whether a certain object has been decoded
 #[derive(Debug)]
 pub enum Decoded<'a> {
@@ -83,7 +199,10 @@ enum State {
     U16Byte1(U16Value, u8),
     U16(U16Value),
     Byte(ByteValue),
+    /// Only reachable from the flags of the logical screen descriptor
     GlobalPalette(usize),
+    /// Checks that a block follows the logical screen descriptor and global palette
+    HeaderEnd,
     BlockStart(Option<Block>),
     BlockEnd(u8),
     ExtensionBlock(u8),
@@ -91,8 +210,7 @@ enum State {
     LocalPalette(usize),
     LzwInit(u8),
     DecodeSubBlock(usize),
-    FrameDecoded,
-    Trailer
+    FrameDecoded
 }
 use self::State::*;
 
@@ -132,16 +250,63 @@ enum ByteValue {
 pub struct StreamingDecoder {
     state: Option<State>,
     lzw_reader: Option<lzw::Decoder<lzw::LsbReader>>,
+    /// Index of the current image data sub-block
+    sub_block: usize,
+    /// Compressed bytes of the current frame decoded so far
+    data_offset: usize,
+    output_limit: OutputLimit,
+    extension_limit: ExtensionLimit,
+    signature_scan: SignatureScan,
+    /// Bytes skipped in front of the signature
+    junk: usize,
+    /// Decompressed bytes of the current frame
+    frame_output: usize,
+    /// Decompressed bytes of all frames
+    total_output: usize,
     skip_extensions: bool,
+    disposal_check: DisposalCheck,
+    image_data: ImageData,
     version: &'static str,
     width: u16,
     height: u16,
+    /// Only written while parsing the header. Color tables of image descriptors
+    /// are local to their frame, nothing later in the stream can replace this table.
     global_color_table: Rc<Vec<u8>>,
+    global_sorted: bool,
+    /// Sort flag of the local color table of the current frame
+    local_sorted: bool,
     background_color: [u8; 4],
+    background_index: u8,
+    /// Pixel aspect ratio byte of the logical screen descriptor
+    aspect_ratio: u8,
+    /// Packed flags of the logical screen descriptor
+    screen_flags: u8,
     /// ext buffer
     ext: (u8, Vec<u8>, bool),
     /// Frame data
     current: Option<Frame<'static>>,
+    /// Raw flags of the control extension of the current frame
+    control_flags: Option<u8>,
+    /// Loop count of the looping application extension
+    loop_count: Option<u16>,
+    /// Label and content of all extensions, only collected with `Extensions::Save`
+    raw_extensions: Vec<(u8, Vec<u8>)>,
+    /// Sub-block lengths of the extension being decoded
+    ext_sub_blocks: Vec<u8>,
+    /// Sub-block lengths of every extension in `raw_extensions`
+    raw_extension_sub_blocks: Vec<Vec<u8>>,
+    /// All plain text extensions
+    plain_texts: Vec<PlainText>,
+    /// Number of bytes consumed since the start of the stream
+    position: usize,
+    /// Offset of the image separator of the current frame
+    frame_start: usize,
+    /// Offset of the marker of the current block
+    block_start: usize,
+    /// Byte ranges of the image blocks of all decoded frames
+    frame_ranges: Vec<Range<usize>>,
+    /// Compressed sizes of the image data of all decoded frames
+    compressed_sizes: Vec<usize>,
 }
 
 impl SetParameter for StreamingDecoder {}
@@ -152,17 +317,63 @@ impl StreamingDecoder {
         StreamingDecoder {
             state: Some(Magic(0, [0; 6])),
             lzw_reader: None,
+            sub_block: 0,
+            data_offset: 0,
+            output_limit: OutputLimit::default(),
+            extension_limit: ExtensionLimit::default(),
+            signature_scan: SignatureScan::default(),
+            junk: 0,
+            frame_output: 0,
+            total_output: 0,
             skip_extensions: true,
+            disposal_check: DisposalCheck::Lenient,
+            image_data: ImageData::Decode,
             version: "",
             width: 0,
             height: 0,
             global_color_table: Rc::new(Vec::new()),
+            global_sorted: false,
+            local_sorted: false,
             background_color: [0, 0, 0, 0xFF],
+            background_index: 0,
+            aspect_ratio: 0,
+            screen_flags: 0,
             ext: (0, Vec::with_capacity(256), true), // 0xFF + 1 byte length
-            current: None
+            current: None,
+            control_flags: None,
+            loop_count: None,
+            raw_extensions: Vec::new(),
+            ext_sub_blocks: Vec::new(),
+            raw_extension_sub_blocks: Vec::new(),
+            plain_texts: Vec::new(),
+            position: 0,
+            frame_start: 0,
+            block_start: 0,
+            frame_ranges: Vec::new(),
+            compressed_sizes: Vec::new()
         }
     }
     
+    /// Resets the decoder such that it can decode another GIF stream.
+    ///
+    /// All information about the previous image is discarded, the configuration
+    /// of the decoder is kept. This allows to decode concatenated GIF streams.
+    pub fn reset(&mut self) {
+        let skip_extensions = self.skip_extensions;
+        let output_limit = self.output_limit;
+        let disposal_check = self.disposal_check;
+        let image_data = self.image_data;
+        let extension_limit = self.extension_limit;
+        let signature_scan = self.signature_scan;
+        *self = StreamingDecoder::new();
+        self.skip_extensions = skip_extensions;
+        self.output_limit = output_limit;
+        self.disposal_check = disposal_check;
+        self.image_data = image_data;
+        self.extension_limit = extension_limit;
+        self.signature_scan = signature_scan;
+    }
+
     /// Updates the internal state of the decoder. 
     ///
     /// Returns the number of bytes consumed from the input buffer 
@@ -206,11 +417,67 @@ impl StreamingDecoder {
         
     }
     
+    /// Version of the GIF format, `"87a"` or `"89a"`
+    ///
+    /// Empty until the signature has been decoded.
+    pub fn version(&self) -> &'static str {
+        self.version
+    }
+
+    /// Pixel aspect ratio byte as stored in the logical screen descriptor
+    ///
+    /// A value of 0 means that no aspect ratio is given. Otherwise the aspect ratio
+    /// is `(value + 15) / 64`.
+    pub fn aspect_ratio(&self) -> u8 {
+        self.aspect_ratio
+    }
+
+    /// Error describing a premature end of the input in the current state
+    ///
+    /// Distinguishes an incomplete signature (which usually means that the input is not
+    /// a GIF file at all) from a stream that ends within the image data. All cases but the
+    /// incomplete signature are `io::ErrorKind::UnexpectedEof` errors, as more data might
+    /// complete the stream.
+    pub fn unexpected_eof(&self) -> DecodingError {
+        let msg = match self.state {
+            Some(Magic(..)) => return DecodingError::Format("incomplete GIF signature"),
+            Some(LzwInit(_)) | Some(DecodeSubBlock(_)) | Some(FrameDecoded) =>
+                "unexpected EOF in image data",
+            _ => "unexpected EOF"
+        };
+        DecodingError::Io(io::Error::new(io::ErrorKind::UnexpectedEof, msg))
+    }
+
+    /// Returns `true` once the trailer has been decoded
+    ///
+    /// No further data is consumed afterwards, unless the decoder is `reset`.
+    pub fn is_finished(&self) -> bool {
+        self.state.is_none()
+    }
+
+    /// Returns `true` if the data ends after a complete block or the trailer
+    ///
+    /// The end of the data after a block is treated as an implicit trailer, as some
+    /// encoders omit the trailer after the last frame.
+    pub fn at_block_boundary(&self) -> bool {
+        match self.state {
+            Some(BlockEnd(0)) | None => true,
+            _ => false
+        }
+    }
+
     /// Index of the background color in the global palette
+    ///
+    /// The index is returned as stored in the file, such that it round-trips with
+    /// `Encoder::set_background_color` even if the palette contains duplicate colors.
+    /// Returns 0 if the index lies outside of the global palette.
     pub fn bg_color(&self) -> usize {
-        self.global_color_table.chunks(PLTE_CHANNELS).position(
-            |v| v == &self.background_color[..3] 
-        ).unwrap_or(0) as usize
+        let idx = self.background_index as usize;
+        if idx < self.global_color_table.len() / PLTE_CHANNELS {
+            idx
+        } else {
+            0
+        }
     }
     
     /// Returns the data of the last extension that has been decoded.
@@ -240,22 +507,128 @@ impl StreamingDecoder {
         self.height
     }
 
+    /// True if the local color table of the current frame is flagged as sorted
+    pub fn local_palette_sorted(&self) -> bool {
+        self.local_sorted
+    }
+
+    /// Raw packed flags of the graphic control extension of the current frame
+    ///
+    /// Returns `None` if the current frame has no control extension.
+    pub fn control_flags(&self) -> Option<u8> {
+        self.control_flags
+    }
+
+    /// Label and content of all extensions decoded so far, in order of appearance
+    ///
+    /// The content excludes the sub-block lengths. Only collected if the decoder
+    /// is configured with `Extensions::Save`.
+    pub fn raw_extensions(&self) -> &[(u8, Vec<u8>)] {
+        &self.raw_extensions
+    }
+
+    /// Label and data sub-blocks of all extensions decoded so far, in order of appearance
+    ///
+    /// Unlike `Self::raw_extensions` this preserves the boundaries of the sub-blocks.
+    /// Only collected if the decoder is configured with `Extensions::Save`.
+    pub fn raw_extension_sub_blocks(&self) -> Vec<(u8, Vec<Vec<u8>>)> {
+        self.raw_extensions.iter().zip(self.raw_extension_sub_blocks.iter()).map(
+            |(&(label, ref content), lengths)| {
+                let mut rest = &content[..];
+                (label, lengths.iter().map(|&len| {
+                    let (sub_block, tail) = rest.split_at(len as usize);
+                    rest = tail;
+                    sub_block.to_vec()
+                }).collect())
+            }
+        ).collect()
+    }
+
+    /// Loop count of the animation if a looping extension has been decoded
+    ///
+    /// A loop count of 0 means infinite looping.
+    pub fn loop_count(&self) -> Option<u16> {
+        self.loop_count
+    }
+
+    /// Global color table decoded so far, empty if there is none
+    pub fn global_palette(&self) -> &[u8] {
+        &self.global_color_table
+    }
+
+    /// True if the global color table is flagged as sorted
+    pub fn global_palette_sorted(&self) -> bool {
+        self.global_sorted
+    }
+
+    /// Raw packed flags of the logical screen descriptor
+    ///
+    /// Contains the global color table flag, the color resolution, the sort flag
+    /// and the size of the global color table.
+    pub fn screen_descriptor_flags(&self) -> u8 {
+        self.screen_flags
+    }
+
+    /// All plain text extensions decoded so far, in order of appearance
+    pub fn plain_texts(&self) -> &[PlainText] {
+        &self.plain_texts
+    }
+
+    /// Byte ranges of the image blocks of all completely decoded frames
+    ///
+    /// A range covers the image descriptor, the local color table and the image data
+    /// including its block terminator. Offsets are relative to the start of the stream.
+    pub fn frame_ranges(&self) -> &[Range<usize>] {
+        &self.frame_ranges
+    }
+
+    /// Whether the image data is decompressed
+    pub fn image_data(&self) -> ImageData {
+        self.image_data
+    }
+
+    /// Number of bytes consumed since the start of the stream
+    pub fn bytes_consumed(&self) -> usize {
+        self.position
+    }
+
+    /// Offset of the marker of the block started by the last `Decoded::BlockStart`
+    ///
+    /// The offset is relative to the start of the stream.
+    pub fn block_offset(&self) -> usize {
+        self.block_start
+    }
+
+    /// Compressed sizes of the image data of all frames whose data has been read completely
+    ///
+    /// The size is the number of LZW data bytes as stored, the sub-block length bytes
+    /// are not included.
+    pub fn compressed_sizes(&self) -> &[usize] {
+        &self.compressed_sizes
+    }
+
     fn next_state<'a>(&'a mut self, buf: &[u8]) -> Result<(usize, Decoded<'a>), DecodingError> {
         macro_rules! goto (
             ($n:expr, $state:expr) => ({
+                let n = $n;
                 self.state = Some($state); 
-                Ok(($n, Decoded::Nothing))
+                self.position += n;
+                Ok((n, Decoded::Nothing))
             });
             ($state:expr) => ({
                 self.state = Some($state); 
+                self.position += 1;
                 Ok((1, Decoded::Nothing))
             });
             ($n:expr, $state:expr, emit $res:expr) => ({
+                let n = $n;
                 self.state = Some($state); 
-                Ok(($n, $res))
+                self.position += n;
+                Ok((n, $res))
             });
             ($state:expr, emit $res:expr) => ({
                 self.state = Some($state); 
+                self.position += 1;
                 Ok((1, $res))
             })
         );
@@ -267,23 +640,34 @@ impl StreamingDecoder {
         //println!("{:?}", state);
         
         match state {
-            Magic(i, mut version) => if i < 6 {
+            Magic(i, mut version) => if i < 3 && b != b"GIF"[i] && self.signature_scan.0 > 0 {
+                // Skip junk, a new signature can only start at this byte if it is a 'G'.
+                // The scan ends at "GIF", an unknown version is reported as such.
+                let (skipped, next) = if b == b'G' { (i, 1) } else { (i + 1, 0) };
+                self.junk += skipped;
+                if self.junk > self.signature_scan.0 {
+                    return Err(DecodingError::Format("GIF signature not found"))
+                }
+                version[0] = b;
+                goto!(Magic(next, version))
+            } else if i < 6 {
                 version[i] = b;
                 goto!(Magic(i+1, version))
             } else if &version[..3] == b"GIF" {
                 self.version = match &version[3..] {
                     b"87a" => "87a",
                     b"89a" => "89a",
-                    _ => return Err(DecodingError::Format("unsupported GIF version"))
+                    _ => return Err(DecodingError::UnsupportedVersion([version[3], version[4], version[5]]))
                 };
                 goto!(U16Byte1(U16Value::ScreenWidth, b))
             } else {
-                Err(DecodingError::Format("malformed GIF header"))
+                Err(DecodingError::Format("not a GIF file"))
             },
             U16(next) => goto!(U16Byte1(next, b)),
             U16Byte1(next, value) => {
                 use self::U16Value::*;
-                let value = ((b as u16) << 8) | value as u16;
+                // `value` holds the low byte, which arrived in the previous state
+                let value = u16_from_le([value, b]);
                 match (next, value) {
                     (ScreenWidth, width) => {
                         self.width = width;
@@ -321,7 +705,9 @@ impl StreamingDecoder {
                 use self::ByteValue::*;
                 match value {
                     GlobalFlags => {
+                        self.screen_flags = b;
                         let global_table = b & 0x80 != 0;
+                        self.global_sorted = b & 0x08 != 0;
                         let entries = if global_table {
                             let entries = PLTE_CHANNELS*(1 << ((b & 0b111) + 1) as usize);
                             self.global_color_table.make_unique().reserve_exact(entries);
@@ -333,14 +719,21 @@ impl StreamingDecoder {
                     },
                     Background { table_size } => {
                         self.background_color[0] = b;
+                        self.background_index = b;
                         goto!(Byte(AspectRatio { table_size: table_size }))
                     },
                     AspectRatio { table_size } => {
-                        goto!(GlobalPalette(table_size))
+                        self.aspect_ratio = b;
+                        if table_size > 0 {
+                            goto!(GlobalPalette(table_size))
+                        } else {
+                            goto!(HeaderEnd, emit self.finish_global_palette())
+                        }
                     },
                     ControlFlags => {
                         self.ext.1.push(b);
                         let control_flags = b;
+                        self.control_flags = Some(b);
                         if control_flags & 1 != 0 {
                             // Set to Some(...), gets overwritten later
                             self.current_frame_mut().transparent = Some(0)
@@ -351,6 +744,9 @@ impl StreamingDecoder {
                             (control_flags & 0b11100) >> 2
                         ) {
                             Some(method) => method,
+                            None if self.disposal_check == DisposalCheck::Lenient => {
+                                DisposalMethod::Any
+                            },
                             None => return Err(DecodingError::Format(
                                 "unknown disposal method"
                             ))
@@ -368,9 +764,11 @@ impl StreamingDecoder {
                     ImageFlags => {
                         let local_table = (b & 0b1000_0000) != 0;
                         let interlaced   = (b & 0b0100_0000) != 0;
+                        let sorted      = (b & 0b0010_0000) != 0;
                         let table_size  =  b & 0b0000_0111;
                         
                         self.current_frame_mut().interlaced = interlaced;
+                        self.local_sorted = local_table && sorted;
                         if local_table {
                             let entries = PLTE_CHANNELS * (1 << (table_size + 1));
                             
@@ -386,31 +784,46 @@ impl StreamingDecoder {
             }
             GlobalPalette(left) => {
                 let n = cmp::min(left, buf.len());
-                if left > 0 {
-                    self.global_color_table.make_unique().extend(buf[..n].iter().cloned());
+                self.global_color_table.make_unique().extend(buf[..n].iter().cloned());
+                if left > n {
                     goto!(n, GlobalPalette(left - n))
                 } else {
-                    let idx = self.background_color[0];
-                    match self.global_color_table.chunks(PLTE_CHANNELS).nth(idx as usize) {
-                        Some(chunk) => for i in 0..PLTE_CHANNELS {
-                            self.background_color[i] = chunk[i]
-                        },
-                        None => self.background_color[0] = 0
-                    }
-                    goto!(BlockStart(Block::from_u8(b)), emit Decoded::GlobalPalette(
-                        self.global_color_table.clone()
+                    // Emitted right away such that the header can be read without
+                    // any data following it
+                    goto!(n, HeaderEnd, emit self.finish_global_palette())
+                }
+            }
+            HeaderEnd => {
+                // A table size flag not matching the actual table shifts the following
+                // block, such that no valid block marker is found
+                if Block::from_u8(b).is_none() {
+                    return Err(DecodingError::Format(
+                        "global color table size does not match its data"
                     ))
                 }
+                goto!(0, BlockEnd(0))
             }
             BlockStart(type_) => {
                 use common::Block::*;
                 match type_ {
                     Some(Image) => {
+                        // The image separator has already been consumed
+                        self.frame_start = self.position - 1;
+                        self.block_start = self.frame_start;
                         self.add_frame();
                         goto!(U16Byte1(U16Value::ImageLeft, b), emit Decoded::BlockStart(Image))
                     }
-                    Some(Extension) => goto!(ExtensionBlock(b), emit Decoded::BlockStart(Extension)),
-                    Some(Trailer) => goto!(0, State::Trailer, emit Decoded::BlockStart(Trailer)),
+                    Some(Extension) => {
+                        self.block_start = self.position - 1;
+                        goto!(ExtensionBlock(b), emit Decoded::BlockStart(Extension))
+                    }
+                    Some(Trailer) => {
+                        // The trailer is consumed and the stream is finished
+                        self.block_start = self.position;
+                        self.state = None;
+                        self.position += 1;
+                        Ok((1, Decoded::BlockStart(Trailer)))
+                    }
                     None => {
                         return Err(DecodingError::Format(
                         "unknown block type encountered"
@@ -433,8 +846,11 @@ impl StreamingDecoder {
             ExtensionBlock(type_) => {
                 use common::Extension::*;
                 self.ext.0 = type_;
+                // Only the content of the sub-blocks is accumulated, their lengths are
+                // kept separately
                 self.ext.1.clear();
-                self.ext.1.push(b);
+                self.ext_sub_blocks.clear();
+                self.ext_sub_blocks.push(b);
                 if let Some(ext) = Extension::from_u8(type_) {
                     match ext {
                         Control => {
@@ -445,22 +861,38 @@ impl StreamingDecoder {
                         }
                     }
                 } else {
-                    return Err(DecodingError::Format(
-                        "unknown extention block encountered"
-                    ))
+                    // Unknown extensions are skipped, their data is kept as is
+                    goto!(SkipBlock(b as usize))
                 }
             }
             SkipBlock(left) => {
                 let n = cmp::min(left, buf.len());
                 if left > 0 {
-                    self.ext.1.push(b);
+                    if self.ext.1.len() + n > self.extension_limit.0 {
+                        return Err(DecodingError::Format(
+                            "extension exceeds the size limit"
+                        ))
+                    }
+                    self.ext.1.extend(buf[..n].iter().cloned());
                     goto!(n, SkipBlock(left - n))
                 } else {
                     if b == 0 {
                         self.ext.2 = true;
+                        if self.ext.0 == Extension::Application as u8 {
+                            self.read_loop_count()
+                        } else if self.ext.0 == Extension::Text as u8 {
+                            if let Some(text) = PlainText::from_extension(&self.ext.1) {
+                                self.plain_texts.push(text)
+                            }
+                        }
+                        if !self.skip_extensions {
+                            self.raw_extensions.push((self.ext.0, self.ext.1.clone()));
+                            self.raw_extension_sub_blocks.push(self.ext_sub_blocks.clone())
+                        }
                         goto!(BlockEnd(b), emit Decoded::BlockFinished(self.ext.0, &self.ext.1))
                     } else {
                         self.ext.2 = false;
+                        self.ext_sub_blocks.push(b);
                         goto!(SkipBlock(b as usize), emit Decoded::SubBlockFinished(self.ext.0,&self.ext.1))
                     }
                     
@@ -474,41 +906,119 @@ impl StreamingDecoder {
                         .as_mut().unwrap().extend(buf[..n].iter().cloned());
                     goto!(n, LocalPalette(left - n))
                 } else {
+                    // The table is followed by the code size, a byte out of its range
+                    // indicates that the table size flag does not match the actual table.
+                    // This is only a heuristic, a wrong flag may still hit a valid size.
+                    if b > MAX_MIN_CODE_SIZE {
+                        return Err(DecodingError::Format(
+                            "local color table size does not match its data"
+                        ))
+                    }
                     goto!(LzwInit(b))
                 }
             }
             LzwInit(code_size) => {
-                self.lzw_reader = Some(lzw::Decoder::new(lzw::LsbReader::new(), code_size));
+                if code_size > MAX_MIN_CODE_SIZE {
+                    return Err(DecodingError::Format("invalid minimum code size"))
+                }
+                // The data of frames without pixels is skipped without decompressing it
+                self.lzw_reader = if self.current_frame().is_empty()
+                                  || self.image_data == ImageData::Skip {
+                    None
+                } else {
+                    Some(lzw::Decoder::new(lzw::LsbReader::new(), code_size))
+                };
+                self.sub_block = 0;
+                self.data_offset = 0;
+                self.frame_output = 0;
                 goto!(DecodeSubBlock(b as usize), emit Decoded::Frame(self.current_frame_mut()))
             }
             DecodeSubBlock(left) => {
                 if left > 0 {
                     let n = cmp::min(left, buf.len());
-                    let decoder = self.lzw_reader.as_mut().unwrap();
-                    let (consumed, bytes) = try!(decoder.decode_bytes(&buf[..n]));
+                    let decoder = match self.lzw_reader.as_mut() {
+                        Some(decoder) => decoder,
+                        None => {
+                            self.data_offset += n;
+                            return goto!(n, DecodeSubBlock(left - n))
+                        }
+                    };
+                    let (consumed, bytes) = match decoder.decode_bytes(&buf[..n]) {
+                        Ok(result) => result,
+                        Err(err) => return Err(DecodingError::Lzw {
+                            sub_block: self.sub_block,
+                            offset: self.data_offset,
+                            error: err
+                        })
+                    };
+                    self.data_offset += consumed;
+                    self.frame_output += bytes.len();
+                    self.total_output += bytes.len();
+                    if self.frame_output > self.output_limit.per_frame {
+                        return Err(DecodingError::Format(
+                            "frame exceeds the output limit"
+                        ))
+                    } else if self.total_output > self.output_limit.total {
+                        return Err(DecodingError::Format(
+                            "image exceeds the output limit"
+                        ))
+                    }
                     goto!(consumed, DecodeSubBlock(left - consumed), emit Decoded::Data(bytes))
                 }  else if b != 0 { // decode next sub-block
+                    self.sub_block += 1;
                     goto!(DecodeSubBlock(b as usize))
                 } else {
+                    // The last codes may still be buffered by the bit reader after all
+                    // bytes of the data have been consumed
+                    let pixels = {
+                        let frame = self.current_frame();
+                        frame.width as usize * frame.height as usize
+                    };
+                    if self.frame_output < pixels {
+                        if let Some(decoder) = self.lzw_reader.as_mut() {
+                            let bytes = match decoder.decode_bytes(&[]) {
+                                Ok((_, bytes)) => bytes,
+                                Err(err) => return Err(DecodingError::Lzw {
+                                    sub_block: self.sub_block,
+                                    offset: self.data_offset,
+                                    error: err
+                                })
+                            };
+                            if !bytes.is_empty() {
+                                self.frame_output += bytes.len();
+                                self.total_output += bytes.len();
+                                return goto!(0, DecodeSubBlock(0), emit Decoded::Data(bytes))
+                            }
+                        }
+                    }
                     // end of image data reached
+                    self.compressed_sizes.push(self.data_offset);
                     self.current = None;
+                    self.control_flags = None;
                     goto!(0, FrameDecoded, emit Decoded::DataEnd)
                 }
             }
             FrameDecoded => {
+                // The range ends after the block terminator `b`
+                self.frame_ranges.push(self.frame_start..self.position + 1);
                 goto!(BlockEnd(b))
             }
-            Trailer => {
-                self.state = None;
-                Ok((1, Decoded::Trailer))
-                //panic!("EOF {:?}", self)
-            }
         }
     }
     
+    fn finish_global_palette(&mut self) -> Decoded<'static> {
+        let idx = self.background_color[0];
+        match self.global_color_table.chunks(PLTE_CHANNELS).nth(idx as usize) {
+            Some(chunk) => for i in 0..PLTE_CHANNELS {
+                self.background_color[i] = chunk[i]
+            },
+            None => self.background_color[0] = 0
+        }
+        Decoded::GlobalPalette(self.global_color_table.clone())
+    }
+
     fn read_control_extension(&mut self, b: u8) -> Result<State, DecodingError> {
         self.add_frame();
-        self.ext.1.push(b);
         if b != 4 {
             return Err(DecodingError::Format(
                 "control extension has wrong length"
@@ -517,6 +1027,15 @@ impl StreamingDecoder {
         Ok(Byte(ByteValue::ControlFlags))
     }
     
+    /// Extracts the loop count from a NETSCAPE2.0 or ANIMEXTS1.0 application extension
+    fn read_loop_count(&mut self) {
+        let data = &self.ext.1;
+        if data.len() >= 14 && data[11] == 1
+        && (&data[..11] == &APP_NETSCAPE[..] || &data[..11] == &APP_ANIMEXTS[..]) {
+            self.loop_count = (&data[12..14]).read_le().ok()
+        }
+    }
+
     fn add_frame(&mut self) {
         if self.current.is_none() {
             self.current = Some(Frame::default())
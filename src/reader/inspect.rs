@@ -0,0 +1,154 @@
+//! Listing of the block structure of a GIF stream
+
+use std::io;
+use std::io::prelude::*;
+
+use traits::SetParameter;
+use super::{StreamingDecoder, Decoded, DecodingError, ImageData};
+
+/// Block of a GIF stream as listed by `inspect`
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockInfo {
+    /// Signature and logical screen descriptor
+    Header {
+        /// Version of the GIF format, `"87a"` or `"89a"`
+        version: &'static str,
+        /// Width of the logical screen
+        width: u16,
+        /// Height of the logical screen
+        height: u16,
+    },
+    /// Global color table of the given length in bytes
+    GlobalPalette(usize),
+    /// Image descriptor followed by the local color table and the image data
+    Image {
+        /// Offset from the left border of the logical screen
+        left: u16,
+        /// Offset from the top border of the logical screen
+        top: u16,
+        /// Width of the image
+        width: u16,
+        /// Height of the image
+        height: u16,
+        /// Whether the image is interlaced
+        interlaced: bool,
+        /// Length of the local color table in bytes, 0 if there is none
+        local_palette_len: usize,
+    },
+    /// Extension with its label and the length of its content in bytes
+    ///
+    /// The length does not include the sub-block length bytes.
+    Extension {
+        /// Label identifying the extension
+        label: u8,
+        /// Length of the content
+        len: usize,
+    },
+    /// Trailer terminating the stream
+    Trailer,
+}
+
+/// Lists the blocks of a GIF stream together with their byte offsets
+///
+/// The image data is skipped without decompressing it. Meant for debugging malformed
+/// files: the blocks read before an error occurred are not returned, but decoding the
+/// same stream with `Decoder` reports where the error occurred.
+pub fn inspect<R: Read>(r: R) -> Result<Vec<(usize, BlockInfo)>, DecodingError> {
+    let mut reader = io::BufReader::new(r);
+    let mut decoder = StreamingDecoder::new();
+    decoder.set(ImageData::Skip);
+    let mut blocks = Vec::new();
+    loop {
+        let (consumed, block) = {
+            let buf = try!(reader.fill_buf());
+            if buf.len() == 0 {
+                if decoder.at_block_boundary() {
+                    // Missing trailer
+                    break
+                }
+                return Err(decoder.unexpected_eof())
+            }
+            let (consumed, result) = try!(decoder.update(buf));
+            (consumed, match result {
+                Decoded::GlobalPalette(palette) => Some(BlockInfo::GlobalPalette(palette.len())),
+                Decoded::Frame(frame) => Some(BlockInfo::Image {
+                    left: frame.left,
+                    top: frame.top,
+                    width: frame.width,
+                    height: frame.height,
+                    interlaced: frame.interlaced,
+                    local_palette_len: frame.palette.as_ref().map_or(0, |p| p.len())
+                }),
+                Decoded::BlockFinished(label, data) => Some(BlockInfo::Extension {
+                    label: label,
+                    len: data.len()
+                }),
+                Decoded::BlockStart(::common::Block::Trailer) => Some(BlockInfo::Trailer),
+                _ => None
+            })
+        };
+        reader.consume(consumed);
+        match block {
+            Some(BlockInfo::GlobalPalette(len)) => {
+                blocks.push((0, BlockInfo::Header {
+                    version: decoder.version(),
+                    width: decoder.width(),
+                    height: decoder.height()
+                }));
+                if len > 0 {
+                    // Follows the signature and the logical screen descriptor
+                    blocks.push((13, BlockInfo::GlobalPalette(len)))
+                }
+            },
+            Some(BlockInfo::Trailer) => {
+                blocks.push((decoder.block_offset(), BlockInfo::Trailer));
+                break
+            },
+            Some(block) => blocks.push((decoder.block_offset(), block)),
+            None => ()
+        }
+    }
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+
+    use encoder::Encoder;
+
+    use super::{inspect, BlockInfo};
+
+    #[test]
+    fn test_inspect_sample() {
+        let blocks = inspect(File::open("tests/samples/sample_1.gif").unwrap()).unwrap();
+        assert_eq!(blocks, vec![
+            (0, BlockInfo::Header { version: "89a", width: 10, height: 10 }),
+            (13, BlockInfo::GlobalPalette(12)),
+            (25, BlockInfo::Extension { label: 0xF9, len: 4 }),
+            (33, BlockInfo::Image {
+                left: 0, top: 0, width: 10, height: 10, interlaced: false, local_palette_len: 0
+            }),
+            (68, BlockInfo::Trailer),
+        ]);
+    }
+
+    #[test]
+    fn test_inspect_local_palette() {
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 2, 2).write_global_palette(&[0; 6]).unwrap();
+            encoder.write_raw_extension(0xFE, b"comment").unwrap();
+            encoder.write_indexed_frame(2, 2, &[0, 1, 2, 3], Some(&[0; 12]), &Default::default()).unwrap();
+        }
+        let blocks = inspect(&*data).unwrap();
+        assert_eq!(blocks[2], (19, BlockInfo::Extension { label: 0xFE, len: 7 }));
+        match blocks[4] {
+            (_, BlockInfo::Image { local_palette_len: 12, .. }) => (),
+            ref block => panic!("unexpected block {:?}", block)
+        }
+        assert_eq!(blocks[5], (data.len() - 1, BlockInfo::Trailer));
+        // Truncated files report an error
+        assert!(inspect(&data[..data.len() - 4]).is_err());
+    }
+}
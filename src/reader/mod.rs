@@ -2,16 +2,23 @@ use std::borrow::Cow;
 use std::io;
 use std::cmp;
 use std::mem;
+use std::ops::Range;
 use std::rc::Rc;
+use std::time::Duration;
 use std::io::prelude::*;
 
 use traits::{Parameter, SetParameter};
-use common::Frame;
+use common::{Frame, Palette, PaletteBuilder, DisposalMethod, composite_frame_rgba};
+use encoder::Encoder;
+use text::PlainText;
 use util;
 
 mod decoder;
+mod inspect;
+pub use self::inspect::{inspect, BlockInfo};
 pub use self::decoder::{
-    PLTE_CHANNELS, StreamingDecoder, Decoded, DecodingError, Extensions
+    PLTE_CHANNELS, StreamingDecoder, Decoded, DecodingError, Extensions, OutputLimit,
+    ExtensionLimit, SignatureScan, DisposalCheck, ImageData
 };
 
 const N_CHANNELS: usize = 4;
@@ -36,6 +43,16 @@ pub enum ColorOutput {
     RGBA = 0,
     /// The decoder returns the raw indexed data.
     Indexed = 1,
+    /// The decoder returns indexed data packed at the minimum bit depth of the palette.
+    ///
+    /// Palettes with up to 2, 4 or 16 colors result in 1, 2 or 4 bits per pixel,
+    /// larger palettes in 8 bits per pixel (see `Reader::packed_bit_depth`). The first
+    /// pixel is stored in the most significant bits of a byte and every line is padded
+    /// to a full byte (see `Reader::packed_line_length`). This affects the buffer of the
+    /// `Frame` returned by `Reader::read_next_frame`. `Reader::fill_buffer` returns unpacked
+    /// indices, like `Reader::buffer_size` and `Reader::line_length` describe. Indices that
+    /// do not fit into the bit depth are a format error.
+    PackedIndexed = 2,
 }
 
 impl<R: Read> Parameter<Decoder<R>> for ColorOutput {
@@ -44,13 +61,98 @@ impl<R: Read> Parameter<Decoder<R>> for ColorOutput {
     }
 }
 
+/// Handling of frames that exceed the logical screen
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum FrameBounds {
+    /// Frames are cropped to the logical screen. Matches the behavior of most browsers.
+    ///
+    /// The cropping is applied by `Reader::read_next_frame`, the frame info returned
+    /// by `Reader::next_frame` still describes the full frame. A logical screen with
+    /// a zero width or height is treated as missing, frames are decoded in full then.
+    Clamp,
+    /// A frame exceeding the logical screen is reported as a format error.
+    ///
+    /// Frames of a logical screen with a zero width or height are accepted.
+    Error,
+}
+
+impl<R: Read> Parameter<Decoder<R>> for FrameBounds {
+    fn set_param(self, this: &mut Decoder<R>) {
+        this.frame_bounds = self
+    }
+}
+
+/// Maximum number of frames returned by the `Reader`
+///
+/// Once the limit has been reached, the `Reader` reports that no frames are left
+/// without decoding the remaining data. This is useful for previews of long animations.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct MaxFrames(pub usize);
+
+impl<R: Read> Parameter<Decoder<R>> for MaxFrames {
+    fn set_param(self, this: &mut Decoder<R>) {
+        this.max_frames = self.0
+    }
+}
+
 impl<R: Read> SetParameter for Decoder<R> {}
 
+/// Determines the images produced by `Reader::extract_frames`
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum FrameExtraction {
+    /// Every frame is written as stored, with its own size and palette.
+    Raw,
+    /// Every frame is drawn onto the canvas left by the previous frames, taking their
+    /// disposal methods into account, and the whole logical screen is written.
+    ///
+    /// Frames with `DisposalMethod::Any` are kept like `DisposalMethod::Keep`, which is
+    /// what most viewers do.
+    Composited,
+}
+
+/// Information from the logical screen descriptor and global color table
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    /// Width of the logical screen
+    pub width: u16,
+    /// Height of the logical screen
+    pub height: u16,
+    /// The global color palette if present
+    pub global_palette: Option<Vec<u8>>,
+    /// Index of the background color in the global palette
+    pub background_index: u8,
+    /// Pixel aspect ratio byte, 0 if no aspect ratio is given
+    pub aspect: u8,
+}
+
+/// Global information about a GIF file
+#[derive(Debug, Clone, PartialEq)]
+pub struct GifMetadata {
+    /// Version of the GIF format, `"87a"` or `"89a"`
+    pub version: &'static str,
+    /// Width of the logical screen
+    pub width: u16,
+    /// Height of the logical screen
+    pub height: u16,
+    /// Index of the background color in the global palette
+    pub background_index: u8,
+    /// RGB value of the background color if it is part of the global palette
+    pub background_color: Option<[u8; 3]>,
+    /// Pixel aspect ratio byte, 0 if no aspect ratio is given
+    pub aspect: u8,
+    /// The global color palette if present
+    pub global_palette: Option<Vec<u8>>,
+    /// Loop count of the animation if a looping extension has been read
+    pub loop_count: Option<u16>,
+}
+
 /// GIF decoder
 pub struct Decoder<R: Read> {
     r: R,
     decoder: StreamingDecoder,
     color_output: ColorOutput,
+    frame_bounds: FrameBounds,
+    max_frames: usize,
 }
 
 impl<R: Read> Decoder<R> {
@@ -59,7 +161,9 @@ impl<R: Read> Decoder<R> {
         Decoder {
             r: r,
             decoder: StreamingDecoder::new(),
-            color_output: ColorOutput::Indexed
+            color_output: ColorOutput::Indexed,
+            frame_bounds: FrameBounds::Clamp,
+            max_frames: usize::max_value()
         }
     }
     
@@ -67,7 +171,22 @@ impl<R: Read> Decoder<R> {
     ///
     /// Returns a `Reader`. All decoder configuration has to be done beforehand.
     pub fn read_info(self) -> Result<Reader<R>, DecodingError> {
-        Reader::new(self.r, self.decoder, self.color_output).init()
+        Reader::new(self.r, self.decoder, self.color_output, self.frame_bounds, self.max_frames)
+            .init()
+    }
+
+    /// Reads only the logical screen descriptor and the global color palette
+    ///
+    /// No image data is read, the remaining stream is dropped.
+    pub fn read_header(self) -> Result<Header, DecodingError> {
+        let reader = try!(self.read_info());
+        Ok(Header {
+            width: reader.width(),
+            height: reader.height(),
+            global_palette: reader.global_palette().map(|p| p.to_vec()),
+            background_index: reader.bg_color() as u8,
+            aspect: reader.decoder.decoder.aspect_ratio()
+        })
     }
 }
 
@@ -83,9 +202,12 @@ impl<R: Read> ReadDecoder<R> {
             let (consumed, result) = {
                 let buf = try!(self.reader.fill_buf());
                 if buf.len() == 0 {
-                    /*return Err(DecodingError::Format(
-                        "unexpected EOF"
-                    ))*/
+                    if self.decoder.at_block_boundary() {
+                        // Missing trailer
+                        self.at_eof = true;
+                        return Ok(None)
+                    }
+                    return Err(self.decoder.unexpected_eof())
                 }
                 try!(self.decoder.update(buf))
             };
@@ -110,8 +232,25 @@ impl<R: Read> ReadDecoder<R> {
 pub struct Reader<R: Read> {
     decoder: ReadDecoder<R>,
     color_output: ColorOutput,
+    frame_bounds: FrameBounds,
+    /// Number of frames left before `MaxFrames` is reached
+    frames_left: usize,
+    /// Number of frames read completely
+    frames_read: usize,
+    /// Called with the number of frames and bytes read after every frame
+    progress: Option<Box<FnMut(usize, usize)>>,
     global_palette: Option<Rc<Vec<u8>>>,
+    /// Palette of the current frame, shared with `global_palette` if the frame has no local one
+    palette: Option<Rc<Vec<u8>>>,
     current_frame: Frame<'static>,
+    control_flags: Option<u8>,
+    local_palette_sorted: bool,
+    /// RGBA colors of the current frame indexed by palette index
+    color_lut: Vec<u8>,
+    /// Caller supplied RGBA colors used instead of the global palette
+    palette_cache: Option<Vec<u8>>,
+    /// Palette, transparent index and use of the palette cache `color_lut` is built for
+    lut_key: Option<(Rc<Vec<u8>>, Option<u8>, bool)>,
     buffer: Vec<u8>,
     // Offset in current frame
     offset: usize
@@ -119,7 +258,8 @@ pub struct Reader<R: Read> {
 }
 
 impl<R> Reader<R> where R: Read {
-    fn new(reader: R, decoder: StreamingDecoder, color_output: ColorOutput) -> Reader<R> {
+    fn new(reader: R, decoder: StreamingDecoder, color_output: ColorOutput,
+           frame_bounds: FrameBounds, max_frames: usize) -> Reader<R> {
         Reader {
             decoder: ReadDecoder {
                 reader: io::BufReader::new(reader),
@@ -127,9 +267,19 @@ impl<R> Reader<R> where R: Read {
                 at_eof: false
             },
             global_palette: None,
+            palette: None,
             buffer: Vec::with_capacity(32),
             color_output: color_output,
+            frame_bounds: frame_bounds,
+            frames_left: max_frames,
+            frames_read: 0,
+            progress: None,
             current_frame: Frame::default(),
+            control_flags: None,
+            local_palette_sorted: false,
+            color_lut: vec![0; 256 * N_CHANNELS],
+            palette_cache: None,
+            lut_key: None,
             offset: 0
         }
     }
@@ -143,13 +293,14 @@ impl<R> Reader<R> where R: Read {
                     } else {
                         None
                     };
+                    self.palette = self.global_palette.clone();
                     break
                 },
                 Some(_) => {
                     unreachable!()
                 },
                 None => return Err(DecodingError::Format(
-                    "File does not contain any image data"
+                    "incomplete GIF header"
                 ))
             }
         }
@@ -158,78 +309,340 @@ impl<R> Reader<R> where R: Read {
     
     /// Returns the next frame info
     pub fn next_frame(&mut self) -> Result<Option<&Frame<'static>>, DecodingError> {
+        if self.frames_left == 0 {
+            return Ok(None)
+        }
         loop {
             match try!(self.decoder.decode_next()) {
                 Some(Decoded::Frame(frame)) => {
                     self.current_frame = frame.clone();
-                    if frame.palette.is_none() && self.global_palette.is_none() {
+                    let palette = match frame.palette {
+                        // Consecutive frames often repeat the same local palette
+                        Some(ref table) => match self.palette {
+                            Some(ref palette) if **palette == *table => palette.clone(),
+                            _ => Rc::new(table.clone())
+                        },
+                        None => match self.global_palette {
+                            Some(ref palette) => palette.clone(),
+                            None => return Err(DecodingError::Format(
+                                "No color table available for current frame."
+                            ))
+                        }
+                    };
+                    self.palette = Some(palette);
+                    if self.frame_bounds == FrameBounds::Error && self.exceeds_screen() {
                         return Err(DecodingError::Format(
-                            "No color table available for current frame."
+                            "Frame exceeds the logical screen."
                         ))
                     }
                     break  
                 },
                 Some(_) => (),
                 None => return Ok(None)
-                
             }
         }
+        self.frames_left -= 1;
+        self.control_flags = self.decoder.decoder.control_flags();
+        self.local_palette_sorted = self.decoder.decoder.local_palette_sorted();
+        if self.color_output == ColorOutput::RGBA {
+            self.build_color_lut()
+        }
         Ok(Some(&self.current_frame))
     }
 
+    /// Expands the palette of the current frame into the RGBA lookup table.
+    ///
+    /// Indices outside of the palette are mapped to transparent black. The table is only
+    /// rebuilt if the palette or the transparent index changed since the last frame.
+    fn build_color_lut(&mut self) {
+        let palette = self.palette.clone().unwrap();
+        let cached = self.palette_cache.is_some() && self.current_frame.palette.is_none();
+        let key = (palette, self.current_frame.transparent, cached);
+        if let Some((ref palette, transparent, cached)) = self.lut_key {
+            if Rc::ptr_eq(palette, &key.0) && (transparent, cached) == (key.1, key.2) {
+                return
+            }
+        }
+        let lut = &mut self.color_lut;
+        match self.palette_cache {
+            Some(ref cache) if cached => lut.copy_from_slice(cache),
+            _ => {
+                for v in lut.iter_mut() {
+                    *v = 0
+                }
+                for (rgba, rgb) in lut.chunks_mut(N_CHANNELS).zip(key.0.chunks(PLTE_CHANNELS)) {
+                    if rgb.len() == PLTE_CHANNELS {
+                        rgba[0] = rgb[0];
+                        rgba[1] = rgb[1];
+                        rgba[2] = rgb[2];
+                        rgba[3] = 0xFF;
+                    }
+                }
+            }
+        }
+        if let Some(t) = self.current_frame.transparent {
+            lut[t as usize * N_CHANNELS + 3] = 0x00
+        }
+        self.lut_key = Some(key);
+    }
+
     /// Reads the next frame from the image.
     ///
+    /// Interlaced frames are returned deinterlaced.
     /// Do not call `Self::next_frame` beforehand.
+    ///
+    /// If the input ends within the frame an `io::ErrorKind::UnexpectedEof` error is
+    /// returned. Image data that ends before the frame is complete is a format error.
     pub fn read_next_frame(&mut self) -> Result<Option<&Frame<'static>>, DecodingError> {
         if try!(self.next_frame()).is_some() {
-            let mut vec = vec![0; self.buffer_size()];
+            if self.current_frame.is_empty()
+            || self.decoder.decoder.image_data() == ImageData::Skip {
+                try!(self.skip_frame_data());
+                self.report_progress();
+                return Ok(Some(&self.current_frame))
+            }
+            let line_length = self.current_frame.width as usize * self.channels();
+            let mut vec = vec![0; line_length * self.current_frame.height as usize];
             if !try!(self.fill_buffer(&mut vec)) {
                 return Err(DecodingError::Format(
                     "Image truncated"
                 ))
             }
+            if self.current_frame.interlaced {
+                vec = deinterlace(&vec, line_length, self.current_frame.height as usize);
+            }
             self.current_frame.buffer = Cow::Owned(vec);
+            if self.exceeds_screen() {
+                self.clamp_current_frame()
+            }
+            if self.color_output == ColorOutput::PackedIndexed {
+                let packed = try!(pack_indices(
+                    &self.current_frame.buffer,
+                    self.current_frame.width as usize,
+                    self.packed_bit_depth()
+                ));
+                self.current_frame.buffer = Cow::Owned(packed);
+            }
+            self.report_progress();
             Ok(Some(&self.current_frame))
         } else {
             Ok(None)
         }
     }
     
+    /// Sets the RGBA colors used for frames without a local palette
+    ///
+    /// Skips expanding the global palette for every frame with `ColorOutput::RGBA`,
+    /// e.g. if many images share a known palette. The table is used as is, apart from
+    /// the transparent index of a frame, whose color becomes transparent as usual.
+    /// Frames with a local palette are not affected.
+    pub fn set_palette_cache(&mut self, table: [[u8; 4]; 256]) {
+        self.palette_cache = Some(table.iter().flat_map(|rgba| rgba.iter().cloned()).collect());
+        self.lut_key = None
+    }
+
+    /// Reads all remaining frames
+    ///
+    /// Like `Self::read_next_frame` but collects the frames.
+    pub fn read_all_frames(&mut self) -> Result<Vec<Frame<'static>>, DecodingError> {
+        let mut frames = Vec::new();
+        while try!(self.read_next_frame()).is_some() {
+            frames.push(self.take_current_frame())
+        }
+        Ok(frames)
+    }
+
+    /// Sets a callback to report the progress of long decodes
+    ///
+    /// The callback is invoked whenever `Self::read_next_frame` (and thus
+    /// `Self::read_all_frames`) has read a frame completely. It receives the number of
+    /// frames read so far and the number of bytes consumed from the input, including
+    /// those of the header.
+    pub fn set_progress_callback<F>(&mut self, callback: F) where F: FnMut(usize, usize) + 'static {
+        self.progress = Some(Box::new(callback))
+    }
+
+    fn report_progress(&mut self) {
+        self.frames_read += 1;
+        if let Some(ref mut progress) = self.progress {
+            progress(self.frames_read, self.decoder.decoder.bytes_consumed())
+        }
+    }
+
+    /// Moves the current frame out of the reader.
+    ///
+    /// Avoids cloning the frame returned by `Self::read_next_frame` if ownership is
+    /// needed. The current frame is replaced by an empty default frame.
+    pub fn take_current_frame(&mut self) -> Frame<'static> {
+        mem::replace(&mut self.current_frame, Frame::default())
+    }
+
+    /// Returns `true` if the current frame covers the whole logical screen
+    ///
+    /// Previous frames do not need to be composited below such a frame unless it has
+    /// a transparent color (see `Frame::transparent`).
+    pub fn current_frame_is_fullscreen(&self) -> bool {
+        let frame = &self.current_frame;
+        frame.left == 0 && frame.top == 0
+        && frame.width >= self.width() && frame.height >= self.height()
+    }
+
+    /// True if the current frame exceeds the logical screen.
+    ///
+    /// Some encoders write an empty logical screen, no frame exceeds it.
+    fn exceeds_screen(&self) -> bool {
+        let frame = &self.current_frame;
+        if self.width() == 0 || self.height() == 0 {
+            return false
+        }
+        frame.left as u32 + frame.width as u32 > self.width() as u32
+        || frame.top as u32 + frame.height as u32 > self.height() as u32
+    }
+
+    /// Crops the current frame (including its buffer) to the logical screen.
+    fn clamp_current_frame(&mut self) {
+        let channels = self.channels();
+        let (screen_width, screen_height) = (self.width(), self.height());
+        let frame = &mut self.current_frame;
+        let width = cmp::min(frame.width, screen_width.saturating_sub(frame.left));
+        let height = cmp::min(frame.height, screen_height.saturating_sub(frame.top));
+        let line_length = frame.width as usize * channels;
+        let buffer: Vec<u8> = if line_length == 0 {
+            Vec::new()
+        } else {
+            frame.buffer.chunks(line_length).take(height as usize).flat_map(
+                |line| line[..width as usize * channels].iter().cloned()
+            ).collect()
+        };
+        frame.width = width;
+        frame.height = height;
+        frame.buffer = Cow::Owned(buffer);
+    }
+
+    /// Skips the next frame without decoding its image data into a buffer.
+    ///
+    /// Returns the frame info like `Self::read_next_frame` but with an empty buffer.
+    /// Do not call `Self::next_frame` beforehand.
+    pub fn skip_next_frame(&mut self) -> Result<Option<&Frame<'static>>, DecodingError> {
+        if try!(self.next_frame()).is_some() {
+            try!(self.skip_frame_data());
+            Ok(Some(&self.current_frame))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Discards the remaining data of the current frame and clears its buffer.
+    fn skip_frame_data(&mut self) -> Result<(), DecodingError> {
+        loop {
+            match try!(self.decoder.decode_next()) {
+                Some(Decoded::Data(_)) => (),
+                Some(Decoded::DataEnd) => break,
+                _ => return Err(DecodingError::Format(
+                    "Image truncated"
+                ))
+            }
+        }
+        self.buffer.clear();
+        self.current_frame.buffer = Cow::Borrowed(&[]);
+        Ok(())
+    }
+
+    /// Reads the information of all remaining frames, their buffers are left empty.
+    ///
+    /// Configure the decoder with `ImageData::Skip` to avoid decompressing the image data.
+    pub fn read_frame_infos(&mut self) -> Result<Vec<Frame<'static>>, DecodingError> {
+        let mut frames = Vec::new();
+        while let Some(frame) = try!(self.skip_next_frame()) {
+            frames.push(frame.clone())
+        }
+        Ok(frames)
+    }
+
+    /// Timing of all remaining frames for converting the animation to video
+    ///
+    /// Returns the index, start time and duration of every frame. Like browsers do,
+    /// delays of 0 and 1 hundredths of a second are played as 10 hundredths. The frames
+    /// are read like `Self::read_frame_infos`.
+    pub fn frame_timings(&mut self) -> Result<Vec<(usize, Duration, Duration)>, DecodingError> {
+        let mut start = Duration::from_millis(0);
+        Ok(try!(self.read_frame_infos()).iter().enumerate().map(|(i, frame)| {
+            let delay = if frame.delay < 2 { 10 } else { frame.delay };
+            let duration = Duration::from_millis(delay as u64 * 10);
+            let timing = (i, start, duration);
+            start = start + duration;
+            timing
+        }).collect())
+    }
+
+    /// Returns the underlying reader for reading data following the GIF stream.
+    ///
+    /// The reader is returned wrapped in the `BufReader` used by the decoder since it
+    /// may have read ahead. Once `Self::read_next_frame` returned `None`, the buffered
+    /// reader is positioned right after the trailer of the GIF stream. If it is called
+    /// earlier the reader is positioned somewhere within the GIF stream.
+    pub fn into_inner(self) -> io::BufReader<R> {
+        self.decoder.reader
+    }
+
+    /// Computes the bounding box of all remaining frames.
+    ///
+    /// Returns the union of the frame rectangles as `(left, top, width, height)`
+    /// or `None` if no frames are left. This can be used as the effective canvas
+    /// size of images with a wrong logical screen. The image data of the frames
+    /// is skipped without decompressing it and cannot be read afterwards.
+    pub fn frames_bounding_box(&mut self)
+    -> Result<Option<(u16, u16, u16, u16)>, DecodingError> {
+        let image_data = self.decoder.decoder.image_data();
+        self.decoder.decoder.set(ImageData::Skip);
+        let bounds = self.frames_union();
+        self.decoder.decoder.set(image_data);
+        Ok(try!(bounds).map(|(l, t, r, b)| (
+            l as u16, t as u16,
+            cmp::min(r - l, 0xFFFF) as u16, cmp::min(b - t, 0xFFFF) as u16
+        )))
+    }
+
+    /// Union of the remaining frame rectangles as (left, top, right, bottom)
+    fn frames_union(&mut self) -> Result<Option<(u32, u32, u32, u32)>, DecodingError> {
+        let mut bounds: Option<(u32, u32, u32, u32)> = None;
+        while let Some(frame) = try!(self.next_frame()) {
+            let (left, top) = (frame.left as u32, frame.top as u32);
+            let (right, bottom) = (left + frame.width as u32, top + frame.height as u32);
+            bounds = Some(match bounds {
+                Some((l, t, r, b)) => (
+                    cmp::min(l, left), cmp::min(t, top),
+                    cmp::max(r, right), cmp::max(b, bottom)
+                ),
+                None => (left, top, right, bottom)
+            });
+        }
+        Ok(bounds)
+    }
+
     /// Reads data of the current frame into a pre-allocated buffer.
     ///
-    /// `Self::next_frame` needs to be called beforehand. The returned boolean indicates
+    /// `Self::next_frame` needs to be called beforehand. The lines of interlaced frames
+    /// are returned in the order they are stored in. The returned boolean indicates
     /// whether more data is available in the current frame. Should not be called after a `false`
     /// had been returned.
     pub fn fill_buffer(&mut self, mut buf: &mut [u8]) -> Result<bool, DecodingError> {
         use self::ColorOutput::*;
-        const PLTE_CHANNELS: usize = 3;
         macro_rules! handle_data(
             ($data:expr) => {
                 match self.color_output {
                     RGBA => {
-                        let transparent = self.current_frame.transparent;
-                        let palette: &[u8] = match self.current_frame.palette {
-                            Some(ref table) => &*table,
-                            None => &*self.global_palette.as_ref().unwrap(),
-                        };
                         let len = cmp::min(buf.len()/N_CHANNELS, $data.len());
                         for (rgba, &idx) in buf[..len*N_CHANNELS].chunks_mut(N_CHANNELS).zip($data.iter()) {
-                            let plte_offset = PLTE_CHANNELS * idx as usize;
-                            if palette.len() >= plte_offset + PLTE_CHANNELS {
-                                let colors = &palette[plte_offset..];
-                                rgba[0] = colors[0];
-                                rgba[1] = colors[1];
-                                rgba[2] = colors[2];
-                                rgba[3] = if let Some(t) = transparent {
-                                    if t == idx { 0x00 } else { 0xFF }
-                                } else {
-                                    0xFF
-                                }
-                            }
+                            let color = &self.color_lut[idx as usize * N_CHANNELS..];
+                            rgba[0] = color[0];
+                            rgba[1] = color[1];
+                            rgba[2] = color[2];
+                            rgba[3] = color[3];
                         }
                         (len, N_CHANNELS)
                     },
-                    Indexed => {
+                    Indexed | PackedIndexed => {
                         let len = cmp::min(buf.len(), $data.len());
                         util::copy_memory(&$data[..len], &mut buf[..len]);
                         (len, 1)
@@ -265,7 +678,52 @@ impl<R> Reader<R> where R: Read {
             }
         }
     }
+
+    /// Reads the indices of the current frame widened to `u16` into a pre-allocated buffer.
+    ///
+    /// Behaves like `Self::fill_buffer` but always returns the raw indices,
+    /// independent of the configured `ColorOutput`. The buffer has to hold
+    /// `width * height` indices to read the whole frame at once.
+    pub fn fill_buffer_u16(&mut self, mut buf: &mut [u16]) -> Result<bool, DecodingError> {
+        if self.buffer.len() > 0 {
+            let len = cmp::min(buf.len(), self.buffer.len());
+            for (dst, idx) in buf.iter_mut().zip(self.buffer.drain(..len)) {
+                *dst = idx as u16
+            }
+            let buf_ = buf; buf = &mut buf_[len..];
+            if buf.len() == 0 {
+                return Ok(true)
+            }
+        }
+        loop {
+            match try!(self.decoder.decode_next()) {
+                Some(Decoded::Data(data)) => {
+                    let len = cmp::min(buf.len(), data.len());
+                    for (dst, &idx) in buf.iter_mut().zip(data.iter()) {
+                        *dst = idx as u16
+                    }
+                    let buf_ = buf; buf = &mut buf_[len..];
+                    if buf.len() > 0 {
+                        continue
+                    } else if len < data.len() {
+                        self.buffer.extend(data[len..].iter().cloned());
+                    }
+                    return Ok(true)
+                },
+                Some(_) => return Ok(false),
+                None => return Ok(false)
+            }
+        }
+    }
     
+    /// Number of decoded indices that have not been returned by `Self::fill_buffer` yet
+    ///
+    /// Decoded data that does not fit into the buffer passed to `Self::fill_buffer` is
+    /// kept until the next call.
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len()
+    }
+
     /// Output buffer size
     pub fn buffer_size(&self) -> usize {
         self.line_length() * self.current_frame.height as usize
@@ -273,29 +731,228 @@ impl<R> Reader<R> where R: Read {
     
     /// Line length of the current frame
     pub fn line_length(&self) -> usize {
-        use self::ColorOutput::*;
+        self.current_frame.width as usize * self.channels()
+    }
+
+    /// Line length of the current frame in `ColorOutput::PackedIndexed` mode
+    ///
+    /// Every line of the buffer returned by `Self::read_next_frame` is padded to a full byte.
+    pub fn packed_line_length(&self) -> usize {
+        (self.current_frame.width as usize * self.packed_bit_depth() as usize + 7) / 8
+    }
+
+    /// Bits per pixel of the current frame in `ColorOutput::PackedIndexed` mode
+    pub fn packed_bit_depth(&self) -> u8 {
+        let colors = self.palette.as_ref().map(|p| p.len() / PLTE_CHANNELS).unwrap_or(0);
+        match colors {
+            0...2 => 1,
+            3...4 => 2,
+            5...16 => 4,
+            _ => 8
+        }
+    }
+
+    /// Bytes per pixel while decoding
+    fn channels(&self) -> usize {
         match self.color_output {
-            RGBA => self.current_frame.width as usize * N_CHANNELS,
-            Indexed => self.current_frame.width as usize
+            ColorOutput::RGBA => N_CHANNELS,
+            ColorOutput::Indexed | ColorOutput::PackedIndexed => 1
         }
     }
     
     /// Returns the color palette relevant for the current (next) frame
     pub fn palette(&self) -> Result<&[u8], DecodingError> {
-        // TODO prevent planic
-        Ok(match self.current_frame.palette {
-            Some(ref table) => &*table,
-            None => &*try!(self.global_palette.as_ref().ok_or(DecodingError::Format(
-                "No color table available for current frame."
-            ))),
-        })
+        self.palette.as_ref().map(|v| &***v).ok_or(DecodingError::Format(
+            "No color table available for current frame."
+        ))
     }
     
+    /// Typed view of the color palette relevant for the current (next) frame
+    pub fn palette_colors(&self) -> Result<Palette, DecodingError> {
+        self.palette().map(Palette::new)
+    }
+
+    /// Raw packed flags of the graphic control extension of the current frame
+    ///
+    /// Contains the disposal method, user input and transparency bits exactly as
+    /// stored in the file. Returns `None` if the frame has no control extension.
+    pub fn current_control_flags(&self) -> Option<u8> {
+        self.control_flags
+    }
+
+    /// True if the local color palette of the current frame is flagged as sorted
+    pub fn current_palette_sorted(&self) -> bool {
+        self.local_palette_sorted
+    }
+
+    /// True if the current frame has its own local color palette
+    pub fn current_frame_has_local_palette(&self) -> bool {
+        self.current_frame.palette.is_some()
+    }
+
     /// The global color palette
     pub fn global_palette(&self) -> Option<&[u8]> {
         self.global_palette.as_ref().map(|v| &***v)
     }
 
+    /// Label and content of all extensions read so far, in order of appearance
+    ///
+    /// Requires the decoder to be configured with `Extensions::Save`. The extensions,
+    /// including unknown ones, can be re-emitted using `HeaderWritten::write_raw_extension`.
+    pub fn raw_extensions(&self) -> &[(u8, Vec<u8>)] {
+        self.decoder.decoder.raw_extensions()
+    }
+
+    /// Label and data sub-blocks of all extensions read so far, in order of appearance
+    ///
+    /// Requires the decoder to be configured with `Extensions::Save`. Preserves the
+    /// boundaries of the sub-blocks such that `HeaderWritten::write_extension_sub_blocks`
+    /// re-emits the extensions byte for byte.
+    pub fn raw_extension_sub_blocks(&self) -> Vec<(u8, Vec<Vec<u8>>)> {
+        self.decoder.decoder.raw_extension_sub_blocks()
+    }
+
+    /// Loop count of the animation (0 means infinite looping)
+    ///
+    /// Returns `None` if no looping extension has been read yet. The extension
+    /// usually precedes the first frame.
+    pub fn loop_count(&self) -> Option<u16> {
+        self.decoder.decoder.loop_count()
+    }
+
+    /// True if the global color palette is flagged as sorted
+    pub fn global_palette_sorted(&self) -> bool {
+        self.decoder.decoder.global_palette_sorted()
+    }
+
+    /// Raw packed flags byte of the logical screen descriptor
+    ///
+    /// Allows reproducing the color resolution and the other flags verbatim.
+    pub fn screen_descriptor_flags(&self) -> u8 {
+        self.decoder.decoder.screen_descriptor_flags()
+    }
+
+    /// Snapshot of the global information about the image
+    ///
+    /// The loop count is only known once the looping extension has been read,
+    /// which usually directly follows the global palette.
+    pub fn metadata(&self) -> GifMetadata {
+        let background_index = self.bg_color() as u8;
+        GifMetadata {
+            version: self.decoder.decoder.version(),
+            width: self.width(),
+            height: self.height(),
+            background_index: background_index,
+            background_color: self.global_palette().and_then(|palette| {
+                Palette::new(palette).color(background_index as usize)
+            }),
+            aspect: self.decoder.decoder.aspect_ratio(),
+            global_palette: self.global_palette().map(|p| p.to_vec()),
+            loop_count: self.loop_count()
+        }
+    }
+
+    /// All plain text extensions read so far, in order of appearance
+    ///
+    /// Use `PlainText::render` to draw them onto the canvas.
+    pub fn plain_texts(&self) -> &[PlainText] {
+        self.decoder.decoder.plain_texts()
+    }
+
+    /// Byte ranges of the image blocks of all frames read so far
+    ///
+    /// A range covers the image descriptor, the local color table and the image data
+    /// of a frame. The preceding control extension is not included.
+    pub fn frame_ranges(&self) -> &[Range<usize>] {
+        self.decoder.decoder.frame_ranges()
+    }
+
+    /// Compressed sizes of the image data of all frames read so far, in bytes
+    ///
+    /// The sub-block length bytes are not included. The size of a frame is known once
+    /// its data has been read completely, at the latest when the next frame is read.
+    pub fn compressed_sizes(&self) -> &[usize] {
+        self.decoder.decoder.compressed_sizes()
+    }
+
+    /// Screen-sized RGBA canvas filled with the background color, ready for compositing
+    ///
+    /// The canvas is transparent if there is no global palette or if the background
+    /// index is the transparent index of the current frame. Call `next_frame` or
+    /// `read_next_frame` first to take the transparency of the first frame into account.
+    pub fn initial_canvas_rgba(&self) -> Vec<u8> {
+        let index = self.bg_color();
+        let background = self.global_palette().and_then(|palette| {
+            Palette::new(palette).color(index)
+        });
+        let color = match background {
+            Some(rgb) if self.current_frame.transparent != Some(index as u8) => {
+                [rgb[0], rgb[1], rgb[2], 0xFF]
+            },
+            _ => [0; N_CHANNELS]
+        };
+        let pixels = self.width() as usize * self.height() as usize;
+        let mut canvas = Vec::with_capacity(pixels * N_CHANNELS);
+        for _ in 0..pixels {
+            canvas.extend(color.iter().cloned())
+        }
+        canvas
+    }
+
+    /// Extracts the remaining frames as independent single-frame GIF images
+    ///
+    /// Requires `ColorOutput::Indexed` and `ImageData::Decode`. The delay of every frame
+    /// is preserved. The background of composited frames is transparent. If a composited
+    /// frame has more than 256 colors, or more than 255 colors besides transparency, it is
+    /// quantized with `Frame::from_rgba`.
+    pub fn extract_frames(&mut self, mode: FrameExtraction)
+    -> Result<Vec<Vec<u8>>, DecodingError> {
+        if self.color_output != ColorOutput::Indexed {
+            return Err(DecodingError::Internal(
+                "frame extraction requires ColorOutput::Indexed"
+            ))
+        }
+        if self.decoder.decoder.image_data() == ImageData::Skip {
+            return Err(DecodingError::Internal(
+                "frame extraction requires ImageData::Decode"
+            ))
+        }
+        let (width, height) = (self.width(), self.height());
+        let mut canvas = vec![0; width as usize * height as usize * N_CHANNELS];
+        let mut images = Vec::new();
+        while try!(self.read_next_frame()).is_some() {
+            let frame = &self.current_frame;
+            let palette: &[u8] = &**self.palette.as_ref().unwrap();
+            let image = match mode {
+                FrameExtraction::Raw => {
+                    let mut raw = frame.clone();
+                    raw.left = 0;
+                    raw.top = 0;
+                    raw.palette = None;
+                    try!(encode_single_frame(&raw, palette))
+                },
+                FrameExtraction::Composited => {
+                    let previous = if frame.dispose == DisposalMethod::Previous {
+                        Some(canvas.clone())
+                    } else {
+                        None
+                    };
+                    draw_frame(&mut canvas, width, frame, palette);
+                    let image = try!(encode_rgba(width, height, &canvas, frame.delay));
+                    match frame.dispose {
+                        DisposalMethod::Background => clear_frame(&mut canvas, width, height, frame),
+                        DisposalMethod::Previous => canvas = previous.unwrap(),
+                        // No disposal specified, the frame stays like with `Keep`
+                        DisposalMethod::Any | DisposalMethod::Keep => ()
+                    }
+                    image
+                }
+            };
+            images.push(image)
+        }
+        Ok(images)
+    }
+
     /// Width of the image
     pub fn width(&self) -> u16 {
         self.decoder.decoder.width()
@@ -312,14 +969,171 @@ impl<R> Reader<R> where R: Read {
     }
 }
 
+/// Decodes the first frame of a GIF image to RGBA, e.g. for a thumbnail
+///
+/// Returns the width and height of the frame and its pixels. The frame is not composited
+/// onto the logical screen, frames smaller than the screen keep their own size. Images
+/// without any frame are a format error.
+pub fn decode_first_frame_rgba<R: Read>(r: R) -> Result<(u16, u16, Vec<u8>), DecodingError> {
+    let mut decoder = Decoder::new(r);
+    decoder.set(ColorOutput::RGBA);
+    decoder.set(MaxFrames(1));
+    let mut reader = try!(decoder.read_info());
+    match try!(reader.read_next_frame()) {
+        Some(_) => {
+            let frame = reader.take_current_frame();
+            Ok((frame.width, frame.height, frame.buffer.into_owned()))
+        },
+        None => Err(DecodingError::Format("image contains no frames"))
+    }
+}
+
+/// Area of `frame` on a logical screen of the given size, as (left, top, right, bottom)
+fn visible_area(width: u16, height: u16, frame: &Frame) -> (usize, usize, usize, usize) {
+    let left = cmp::min(frame.left, width) as usize;
+    let top = cmp::min(frame.top, height) as usize;
+    let right = cmp::min(left + frame.width as usize, width as usize);
+    let bottom = cmp::min(top + frame.height as usize, height as usize);
+    (left, top, right, bottom)
+}
+
+/// Draws the indexed `frame` onto the RGBA `canvas`, skipping transparent pixels.
+fn draw_frame(canvas: &mut [u8], width: u16, frame: &Frame, palette: &[u8]) {
+    let mut rgba = Vec::with_capacity(frame.buffer.len() * N_CHANNELS);
+    for &idx in frame.buffer.iter() {
+        match palette.chunks(PLTE_CHANNELS).nth(idx as usize) {
+            Some(rgb) if Some(idx) != frame.transparent && rgb.len() == PLTE_CHANNELS => {
+                rgba.extend([rgb[0], rgb[1], rgb[2], 0xFF].iter().cloned())
+            },
+            _ => rgba.extend([0; N_CHANNELS].iter().cloned())
+        }
+    }
+    composite_frame_rgba(canvas, width, &rgba, frame)
+}
+
+/// Clears the area of `frame` on the RGBA `canvas` to transparent.
+fn clear_frame(canvas: &mut [u8], width: u16, height: u16, frame: &Frame) {
+    let (left, top, right, bottom) = visible_area(width, height, frame);
+    for y in top..bottom {
+        let start = (y * width as usize + left) * N_CHANNELS;
+        let end = (y * width as usize + right) * N_CHANNELS;
+        for v in &mut canvas[start..end] {
+            *v = 0
+        }
+    }
+}
+
+/// Encodes an image consisting of `frame` only, using `palette` as the global palette.
+fn encode_single_frame(frame: &Frame, palette: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = try!(
+        Encoder::new(Vec::new(), frame.width, frame.height).write_global_palette(palette)
+    );
+    try!(encoder.write_frame(frame));
+    encoder.into_inner()
+}
+
+/// Encodes RGBA pixels as a single-frame image.
+///
+/// The colors are kept exactly unless there are too many of them, in which case the
+/// pixels are quantized with `Frame::from_rgba`.
+fn encode_rgba(width: u16, height: u16, pixels: &[u8], delay: u16) -> io::Result<Vec<u8>> {
+    let mut builder = PaletteBuilder::new();
+    let mut has_transparency = false;
+    for pix in pixels.chunks(N_CHANNELS) {
+        if pix[3] == 0 {
+            has_transparency = true
+        } else {
+            builder.observe(pix)
+        }
+    }
+    let mut frame = if builder.len() > 256 || (has_transparency && builder.len() == 256) {
+        Frame::from_rgba(width, height, &mut pixels.to_vec())
+    } else {
+        let (mut palette, indices) = builder.build();
+        let transparent = (palette.len() / PLTE_CHANNELS) as u8;
+        let mut frame = Frame::default();
+        frame.width = width;
+        frame.height = height;
+        frame.buffer = Cow::Owned(pixels.chunks(N_CHANNELS).map(|pix| if pix[3] == 0 {
+            transparent
+        } else {
+            indices[&[pix[0], pix[1], pix[2]]]
+        }).collect());
+        if has_transparency {
+            palette.extend([0, 0, 0].iter().cloned());
+            frame.transparent = Some(transparent);
+        }
+        frame.palette = Some(palette);
+        frame
+    };
+    frame.delay = delay;
+    let palette = frame.palette.take().unwrap();
+    encode_single_frame(&frame, &palette)
+}
+
+/// Reorders the lines of an interlaced image into their natural order.
+fn deinterlace(data: &[u8], line_length: usize, height: usize) -> Vec<u8> {
+    let mut result = vec![0; data.len()];
+    if line_length == 0 {
+        return result
+    }
+    let mut lines = data.chunks(line_length);
+    // (first line, step) of the four interlacing passes
+    for &(start, step) in &[(0, 8), (4, 8), (2, 4), (1, 2)] {
+        let mut y = start;
+        while y < height {
+            if let Some(line) = lines.next() {
+                util::copy_memory(line, &mut result[y * line_length..]);
+            }
+            y += step;
+        }
+    }
+    result
+}
+
+/// Packs one byte per pixel indices to `depth` bits per pixel, padding every line.
+fn pack_indices(data: &[u8], width: usize, depth: u8) -> Result<Vec<u8>, DecodingError> {
+    if depth == 8 || width == 0 {
+        return Ok(data.to_vec())
+    }
+    let depth = depth as usize;
+    let line_length = (width * depth + 7) / 8;
+    let mut result = Vec::with_capacity(line_length * (data.len() / width));
+    for line in data.chunks(width) {
+        for pixels in line.chunks(8 / depth) {
+            let mut byte = 0;
+            for (i, &idx) in pixels.iter().enumerate() {
+                if idx >> depth != 0 {
+                    return Err(DecodingError::Format(
+                        "index does not fit into the packed bit depth"
+                    ))
+                }
+                byte |= idx << (8 - depth * (i + 1));
+            }
+            result.push(byte)
+        }
+    }
+    Ok(result)
+}
+
 #[cfg(test)]
 mod test {
     extern crate test;
 
+    use std::cmp;
     use std::fs::File;
+    use std::io;
     use std::io::prelude::*;
 
-    use super::Decoder;
+    use std::borrow::Cow;
+    use std::time::Duration;
+
+    use traits::SetParameter;
+    use common::{Block, Frame, DisposalMethod};
+    use encoder::{Encoder, FrameOptions, TrailerMode, DefaultDisposal};
+    use text::PlainText;
+
+    use super::{decode_first_frame_rgba, Decoder, ColorOutput, Extensions, FrameBounds, FrameExtraction, MaxFrames, StreamingDecoder, Decoded, DecodingError, OutputLimit, ExtensionLimit, SignatureScan, Header, DisposalCheck, GifMetadata, ImageData};
     
     
     #[bench]
@@ -348,23 +1162,1623 @@ mod test {
         b.bytes = decoder.read_next_frame().unwrap().unwrap().buffer.len() as u64
     }
     
+    #[bench]
+    fn bench_big_rgba(b: &mut test::Bencher) {
+        let mut data = Vec::new();
+        File::open("tests/sample_big.gif").unwrap().read_to_end(&mut data).unwrap();
+        b.iter(|| {
+            let mut decoder = Decoder::new(&*data);
+            decoder.set(ColorOutput::RGBA);
+            let mut decoder = decoder.read_info().unwrap();
+            let frame = decoder.read_next_frame().unwrap().unwrap();
+            test::black_box(frame);
+        });
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(ColorOutput::RGBA);
+        let mut decoder = decoder.read_info().unwrap();
+        b.bytes = decoder.read_next_frame().unwrap().unwrap().buffer.len() as u64
+    }
+    
     #[test]
-    fn test_simple_indexed() {
-        let mut decoder = Decoder::new(File::open("tests/samples/sample_1.gif").unwrap()).read_info().unwrap();
-        let frame = decoder.read_next_frame().unwrap().unwrap();
-        assert_eq!(&*frame.buffer, &[
-            1, 1, 1, 1, 1, 2, 2, 2, 2, 2,
-            1, 1, 1, 1, 1, 2, 2, 2, 2, 2,
-            1, 1, 1, 1, 1, 2, 2, 2, 2, 2,
-            1, 1, 1, 0, 0, 0, 0, 2, 2, 2,
-            1, 1, 1, 0, 0, 0, 0, 2, 2, 2,
-            2, 2, 2, 0, 0, 0, 0, 1, 1, 1,
-            2, 2, 2, 0, 0, 0, 0, 1, 1, 1,
+    fn test_read_header() {
+        let mut data = Vec::new();
+        File::open("tests/samples/sample_1.gif").unwrap().read_to_end(&mut data).unwrap();
+        // Logical screen descriptor and global palette
+        data.truncate(13 + 12);
+        let header = Decoder::new(&*data).read_header().unwrap();
+        assert_eq!(header, Header {
+            width: 10,
+            height: 10,
+            global_palette: Some(vec![
+                0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00, 0xFF, 0x00, 0x00, 0x00
+            ]),
+            background_index: 0,
+            aspect: 0
+        });
+    }
+
+    #[test]
+    fn test_metadata() {
+        let decoder = Decoder::new(File::open("tests/samples/sample_1.gif").unwrap())
+            .read_info().unwrap();
+        assert_eq!(decoder.metadata(), GifMetadata {
+            version: "89a",
+            width: 10,
+            height: 10,
+            background_index: 0,
+            background_color: Some([0xFF, 0xFF, 0xFF]),
+            aspect: 0,
+            global_palette: Some(vec![
+                0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00, 0xFF, 0x00, 0x00, 0x00
+            ]),
+            loop_count: None
+        });
+    }
+
+    #[test]
+    fn test_header_only() {
+        let mut data = Vec::new();
+        File::open("tests/samples/sample_1.gif").unwrap().read_to_end(&mut data).unwrap();
+        data.truncate(13 + 12);
+        let decoder = Decoder::new(&*data).read_info().unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (10, 10));
+        assert_eq!(decoder.global_palette().unwrap().len(), 12);
+    }
+
+    #[test]
+    fn test_frameless_gif() {
+        let mut data = Vec::new();
+        {
+            let encoder = Encoder::new(&mut data, 2, 2);
+            let mut encoder = encoder.write_global_palette(&[0; 12]).unwrap();
+            encoder.write_raw_extension(0xFE, b"metadata only").unwrap();
+        }
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(Extensions::Save);
+        let mut decoder = decoder.read_info().unwrap();
+        assert!(decoder.read_next_frame().unwrap().is_none());
+        assert_eq!(decoder.raw_extensions(), &[(0xFE, b"metadata only".to_vec())]);
+        assert!(decoder.read_next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_inconsistent_global_table() {
+        let mut data = Vec::new();
+        File::open("tests/samples/sample_1.gif").unwrap().read_to_end(&mut data).unwrap();
+        // The file has a table of 4 colors, claim 2 and 8 colors instead
+        for &flags in &[0x90, 0x92] {
+            data[10] = flags;
+            let result = Decoder::new(&*data).read_info().and_then(|mut decoder| {
+                decoder.read_next_frame().map(|_| ())
+            });
+            match result {
+                Err(DecodingError::Format("global color table size does not match its data")) => (),
+                result => panic!("unexpected result {:?}", result)
+            }
+        }
+    }
+
+    #[test]
+    fn test_simple_indexed() {
+        let mut decoder = Decoder::new(File::open("tests/samples/sample_1.gif").unwrap()).read_info().unwrap();
+        let frame = decoder.read_next_frame().unwrap().unwrap();
+        assert_eq!(&*frame.buffer, &[
+            1, 1, 1, 1, 1, 2, 2, 2, 2, 2,
+            1, 1, 1, 1, 1, 2, 2, 2, 2, 2,
+            1, 1, 1, 1, 1, 2, 2, 2, 2, 2,
+            1, 1, 1, 0, 0, 0, 0, 2, 2, 2,
+            1, 1, 1, 0, 0, 0, 0, 2, 2, 2,
+            2, 2, 2, 0, 0, 0, 0, 1, 1, 1,
+            2, 2, 2, 0, 0, 0, 0, 1, 1, 1,
             2, 2, 2, 2, 2, 1, 1, 1, 1, 1,
             2, 2, 2, 2, 2, 1, 1, 1, 1, 1,
             2, 2, 2, 2, 2, 1, 1, 1, 1, 1
         ][..])
     }
+
+    #[test]
+    fn test_skip_next_frame() {
+        let mut data = Vec::new();
+        File::open("tests/samples/anim-gr.gif").unwrap().read_to_end(&mut data).unwrap();
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        decoder.read_next_frame().unwrap().unwrap();
+        let expected = decoder.read_next_frame().unwrap().unwrap().clone();
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        assert!(decoder.skip_next_frame().unwrap().unwrap().buffer.is_empty());
+        let frame = decoder.read_next_frame().unwrap().unwrap();
+        assert_eq!((frame.left, frame.top, frame.width, frame.height),
+                   (expected.left, expected.top, expected.width, expected.height));
+        assert_eq!(frame.buffer, expected.buffer);
+    }
+
+    #[test]
+    fn test_extension_payload() {
+        let comment: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let mut data = Vec::new();
+        {
+            let encoder = Encoder::new(&mut data, 1, 1);
+            let mut encoder = encoder.write_global_palette(&[0; 6]).unwrap();
+            encoder.write_raw_extension(0xFE, &comment).unwrap();
+        }
+        let mut decoder = StreamingDecoder::new();
+        let mut buf = &data[..];
+        let mut payload = None;
+        while buf.len() > 0 {
+            let (consumed, result) = decoder.update(buf).unwrap();
+            if let Decoded::BlockFinished(0xFE, ext) = result {
+                payload = Some(ext.to_vec())
+            }
+            if consumed == 0 {
+                break
+            }
+            buf = &buf[consumed..];
+        }
+        assert_eq!(payload, Some(comment));
+    }
+
+    /// Feeds `data` to a `StreamingDecoder` in chunks of at most `chunk` bytes and
+    /// returns the decoded palettes, frame dimensions, extensions and pixel data.
+    fn decode_in_chunks(data: &[u8], chunk: usize) -> Vec<(&'static str, Vec<u8>)> {
+        let mut decoder = StreamingDecoder::new();
+        decoder.set(Extensions::Save);
+        let mut events = Vec::new();
+        let mut buf = data;
+        while buf.len() > 0 {
+            let len = cmp::min(chunk, buf.len());
+            let (consumed, result) = decoder.update(&buf[..len]).unwrap();
+            match result {
+                Decoded::GlobalPalette(palette) => events.push(("palette", palette.to_vec())),
+                Decoded::Frame(frame) => events.push(("frame", vec![
+                    frame.left as u8, frame.top as u8, frame.width as u8, frame.height as u8
+                ])),
+                Decoded::BlockFinished(label, ext) => events.push(("extension", {
+                    let mut ext = ext.to_vec();
+                    ext.insert(0, label);
+                    ext
+                })),
+                Decoded::Data(data) => {
+                    let continued = match events.last_mut() {
+                        Some(&mut ("data", ref mut pixels)) => {
+                            pixels.extend(data.iter().cloned());
+                            true
+                        },
+                        _ => false
+                    };
+                    if !continued {
+                        events.push(("data", data.to_vec()))
+                    }
+                },
+                Decoded::Nothing if consumed == 0 => break,
+                _ => ()
+            }
+            buf = &buf[consumed..];
+        }
+        events
+    }
+
+    #[test]
+    fn test_byte_by_byte_decoding() {
+        for name in &["sample_1.gif", "alpha_gif_a.gif", "anim-gr.gif", "moon_impact.gif"] {
+            let mut data = Vec::new();
+            File::open(format!("tests/samples/{}", name)).unwrap()
+                .read_to_end(&mut data).unwrap();
+            let expected = decode_in_chunks(&data, data.len());
+            assert!(expected.iter().any(|&(event, _)| event == "data"));
+            assert_eq!(decode_in_chunks(&data, 1), expected, "{}", name);
+            assert_eq!(decode_in_chunks(&data, 7), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_streaming_decoder_reset() {
+        let mut data = Vec::new();
+        {
+            let encoder = Encoder::new(&mut data, 1, 1);
+            let mut encoder = encoder.write_global_palette(&[0; 12]).unwrap();
+            encoder.write_loop_count(0).unwrap();
+            encoder.write_indexed_frame(1, 1, &[3], Some(&[1; 12]), &Default::default()).unwrap();
+        }
+        {
+            let encoder = Encoder::new(&mut data, 2, 1);
+            let mut encoder = encoder.write_global_palette(&[2; 6]).unwrap();
+            encoder.write_indexed_frame(2, 1, &[1, 0], None, &Default::default()).unwrap();
+        }
+        let mut decoder = StreamingDecoder::new();
+        let mut buf = &data[..];
+        let mut frames = Vec::new();
+        while buf.len() > 0 {
+            let (consumed, local_palette) = {
+                let (consumed, result) = decoder.update(buf).unwrap();
+                (consumed, match result {
+                    Decoded::Frame(frame) => Some(frame.palette.clone()),
+                    _ => None
+                })
+            };
+            if let Some(local_palette) = local_palette {
+                frames.push((
+                    decoder.width(),
+                    local_palette,
+                    decoder.global_palette().to_vec(),
+                    decoder.loop_count()
+                ));
+            }
+            buf = &buf[consumed..];
+            if decoder.is_finished() {
+                // The first stream has been decoded completely
+                assert!(!decoder.last_ext().1.is_empty());
+                decoder.reset();
+                assert!(!decoder.is_finished());
+                assert_eq!(decoder.width(), 0);
+                assert!(decoder.global_palette().is_empty());
+                assert!(decoder.last_ext().1.is_empty());
+                assert_eq!(decoder.loop_count(), None);
+            }
+        }
+        assert_eq!(frames, vec![
+            (1, Some(vec![1; 12]), vec![0; 12], Some(0)),
+            // Nothing of the first stream leaks into the second one
+            (2, None, vec![2; 6], None)
+        ]);
+    }
+
+    #[test]
+    fn test_streaming_decoder_trailer() {
+        let mut data = Vec::new();
+        File::open("tests/samples/sample_1.gif").unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data.last(), Some(&0x3B));
+        let mut decoder = StreamingDecoder::new();
+        let mut buf = &data[..];
+        let mut trailer = false;
+        while buf.len() > 0 {
+            let (consumed, result) = decoder.update(buf).unwrap();
+            if let Decoded::BlockStart(Block::Trailer) = result {
+                trailer = true
+            }
+            buf = &buf[consumed..];
+        }
+        // The stream ends right after the trailer
+        assert!(trailer);
+        assert_eq!(decoder.bytes_consumed(), data.len());
+        assert!(decoder.at_block_boundary());
+        assert_eq!(decoder.update(&[0x3B]).unwrap().0, 0);
+    }
+
+    #[test]
+    fn test_premature_end_code() {
+        let mut data = Vec::new();
+        data.extend(b"GIF89a\x02\x00\x02\x00\x80\x00\x00".iter().cloned());
+        data.extend([0, 0, 0, 0xFF, 0xFF, 0xFF].iter().cloned());
+        data.extend([0x2C, 0, 0, 0, 0, 2, 0, 2, 0, 0].iter().cloned());
+        // 3 bit codes: clear, 0, end, followed by the invalid code 7
+        data.extend([2, 3, 0x44, 0x0F, 0, 0, 0x3B].iter().cloned());
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        match decoder.read_next_frame() {
+            Err(DecodingError::Lzw { sub_block: 0, .. }) => (),
+            result => panic!("unexpected result {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_incomplete_signature() {
+        for data in [&b"GIF"[..], &b"GIF89"[..]].iter() {
+            match Decoder::new(*data).read_info() {
+                Err(DecodingError::Format("incomplete GIF signature")) => (),
+                Err(err) => panic!("unexpected error {:?}", err),
+                Ok(_) => panic!("truncated signature was accepted")
+            }
+        }
+    }
+
+    #[test]
+    fn test_missing_trailer() {
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 2, 2);
+            encoder.set(TrailerMode::Suppressed);
+            let mut encoder = encoder.write_global_palette(&[0; 6]).unwrap();
+            encoder.write_indexed_frame(2, 2, &[0, 1, 1, 0], None, &Default::default()).unwrap();
+            encoder.write_indexed_frame(2, 2, &[1, 0, 0, 1], None, &Default::default()).unwrap();
+        }
+        assert!(data.last() != Some(&0x3B));
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        assert_eq!(&*decoder.read_next_frame().unwrap().unwrap().buffer, &[0, 1, 1, 0]);
+        assert_eq!(&*decoder.read_next_frame().unwrap().unwrap().buffer, &[1, 0, 0, 1]);
+        assert!(decoder.read_next_frame().unwrap().is_none());
+        assert!(decoder.read_next_frame().unwrap().is_none());
+        // A missing block terminator is still an error
+        data.pop();
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        decoder.read_next_frame().unwrap().unwrap();
+        decoder.read_next_frame().unwrap().unwrap();
+        match decoder.read_next_frame() {
+            Err(DecodingError::Io(ref err)) if err.kind() == io::ErrorKind::UnexpectedEof => (),
+            result => panic!("unexpected result {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_eof_in_image_data() {
+        let mut data = Vec::new();
+        {
+            let encoder = Encoder::new(&mut data, 100, 100);
+            let mut encoder = encoder.write_global_palette(&[0; 12]).unwrap();
+            let indices: Vec<u8> = (0..100 * 100).map(|i| (i * 7 % 4) as u8).collect();
+            encoder.write_indexed_frame(100, 100, &indices, None, &Default::default()).unwrap();
+        }
+        let len = data.len();
+        data.truncate(len / 2);
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        match decoder.read_next_frame() {
+            Err(DecodingError::Io(ref err)) if err.kind() == io::ErrorKind::UnexpectedEof => (),
+            result => panic!("unexpected result {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_truncated_image_data() {
+        let mut data = Vec::new();
+        data.extend(b"GIF89a\x02\x00\x02\x00\x80\x00\x00".iter().cloned());
+        data.extend([0, 0, 0, 0xFF, 0xFF, 0xFF].iter().cloned());
+        data.extend([0x2C, 0, 0, 0, 0, 2, 0, 2, 0, 0].iter().cloned());
+        // 3 bit codes: clear, 0, end, only one of four pixels
+        data.extend([2, 2, 0x44, 0x01, 0, 0x3B].iter().cloned());
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        match decoder.read_next_frame() {
+            Err(DecodingError::Format("Image truncated")) => (),
+            result => panic!("unexpected result {:?}", result)
+        }
+        // Ending within the image data is an EOF error instead
+        let mut decoder = Decoder::new(&data[..data.len() - 3]).read_info().unwrap();
+        match decoder.read_next_frame() {
+            Err(DecodingError::Io(ref err)) if err.kind() == io::ErrorKind::UnexpectedEof => (),
+            result => panic!("unexpected result {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_zero_area_frame() {
+        let mut data = Vec::new();
+        {
+            let encoder = Encoder::new(&mut data, 2, 2);
+            let mut encoder = encoder.write_global_palette(&[0; 12]).unwrap();
+            encoder.write_indexed_frame(2, 2, &[1, 0, 0, 1], None, &Default::default()).unwrap();
+        }
+        // Insert a zero width frame with garbage image data in front of the frame
+        let pos = data.iter().position(|&b| b == 0x2C).unwrap();
+        let empty = [0x2C, 0, 0, 0, 0, 0, 0, 2, 0, 0, 2, 2, 0xFF, 0xFF, 0];
+        for (i, &b) in empty.iter().enumerate() {
+            data.insert(pos + i, b)
+        }
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        {
+            let frame = decoder.read_next_frame().unwrap().unwrap();
+            assert!(frame.is_empty());
+            assert!(frame.buffer.is_empty());
+        }
+        assert_eq!(decoder.buffer_size(), 0);
+        assert_eq!(&*decoder.read_next_frame().unwrap().unwrap().buffer, &[1, 0, 0, 1]);
+        assert!(decoder.read_next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_output_limit() {
+        let mut data = Vec::new();
+        {
+            let encoder = Encoder::new(&mut data, 500, 500);
+            let mut encoder = encoder.write_global_palette(&[0; 12]).unwrap();
+            encoder.write_indexed_frame(500, 500, &vec![3; 500 * 500], None, &Default::default()).unwrap();
+        }
+        // The frame compresses to a fraction of its decoded size
+        assert!(data.len() < 500 * 500 / 100);
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(OutputLimit { per_frame: 10_000, total: usize::max_value() });
+        let mut decoder = decoder.read_info().unwrap();
+        match decoder.read_next_frame() {
+            Err(DecodingError::Format(_)) => (),
+            result => panic!("unexpected result {:?}", result)
+        }
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(OutputLimit { per_frame: 500 * 500, total: 500 * 500 });
+        let mut decoder = decoder.read_info().unwrap();
+        assert!(decoder.read_next_frame().unwrap().is_some());
+    }
+
+    #[test]
+    fn test_reserved_disposal() {
+        let mut data = Vec::new();
+        {
+            let encoder = Encoder::new(&mut data, 2, 2);
+            let mut encoder = encoder.write_global_palette(&[0; 12]).unwrap();
+            encoder.write_indexed_frame(2, 2, &[0, 1, 2, 3], None, &Default::default()).unwrap();
+        }
+        // Set the disposal value of the control extension to 5
+        let pos = data.windows(3).position(|w| w == [0x21, 0xF9, 4]).unwrap();
+        data[pos + 3] = (data[pos + 3] & !0b11100) | (5 << 2);
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        assert_eq!(decoder.read_next_frame().unwrap().unwrap().dispose, DisposalMethod::Any);
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(DisposalCheck::Strict);
+        let mut decoder = decoder.read_info().unwrap();
+        match decoder.read_next_frame() {
+            Err(DecodingError::Format("unknown disposal method")) => (),
+            result => panic!("unexpected result {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_frame_ranges() {
+        let mut data = Vec::new();
+        File::open("tests/samples/anim-gr.gif").unwrap().read_to_end(&mut data).unwrap();
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        let header_len = 13 + decoder.global_palette().map_or(0, |p| p.len());
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push(frame.clone());
+        }
+        let ranges = decoder.frame_ranges().to_vec();
+        assert_eq!(ranges.len(), frames.len());
+        for (range, frame) in ranges.into_iter().zip(frames.iter()) {
+            assert_eq!(data[range.start], 0x2C);
+            // Assemble a file containing only this frame
+            let mut single = data[..header_len].to_vec();
+            single.extend(data[range].iter().cloned());
+            single.push(0x3B);
+            let mut decoder = Decoder::new(&*single).read_info().unwrap();
+            let decoded = decoder.read_next_frame().unwrap().unwrap();
+            assert_eq!(decoded.buffer, frame.buffer);
+            assert_eq!((decoded.left, decoded.top), (frame.left, frame.top));
+        }
+    }
+
+    #[test]
+    fn test_single_global_palette() {
+        let global = &[0, 0, 0, 0xFF, 0xFF, 0xFF];
+        let local = &[0xFF, 0, 0, 0, 0xFF, 0];
+        let mut header = Vec::new();
+        {
+            let encoder = Encoder::new(&mut header, 2, 2);
+            encoder.write_global_palette(local).unwrap().write_trailer().unwrap();
+        }
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 2, 2).write_global_palette(global).unwrap();
+            encoder.write_indexed_frame(2, 2, &[0, 1, 1, 0], Some(local), &Default::default()).unwrap();
+            // A comment that looks like a header with a global color table
+            encoder.write_raw_extension(0xFE, &header).unwrap();
+            encoder.write_indexed_frame(2, 2, &[1, 0, 0, 1], None, &Default::default()).unwrap();
+        }
+
+        let mut decoder = StreamingDecoder::new();
+        let mut buf = &*data;
+        let mut palettes = 0;
+        while buf.len() > 0 {
+            let (consumed, result) = decoder.update(buf).unwrap();
+            if let Decoded::GlobalPalette(ref palette) = result {
+                assert_eq!(&***palette, global);
+                palettes += 1;
+            }
+            buf = &buf[consumed..];
+        }
+        assert_eq!(palettes, 1);
+
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        decoder.read_next_frame().unwrap().unwrap();
+        assert_eq!(decoder.palette().unwrap(), local);
+        assert_eq!(decoder.global_palette().unwrap(), global);
+        decoder.read_next_frame().unwrap().unwrap();
+        assert_eq!(decoder.palette().unwrap(), global);
+        assert_eq!(decoder.global_palette().unwrap(), global);
+        assert!(decoder.read_next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_compressed_sizes() {
+        let mut data = Vec::new();
+        File::open("tests/samples/anim-gr.gif").unwrap().read_to_end(&mut data).unwrap();
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        while let Some(_) = decoder.read_next_frame().unwrap() {}
+        let sizes = decoder.compressed_sizes().to_vec();
+        assert_eq!(sizes.len(), decoder.frame_ranges().len());
+        for (range, &size) in decoder.frame_ranges().iter().zip(sizes.iter()) {
+            // Skip the image descriptor, the local color table and the code size
+            let flags = data[range.start + 9];
+            let mut offset = range.start + 10 + 1;
+            if flags & 0x80 != 0 {
+                offset += 3 << ((flags & 0b111) + 1);
+            }
+            let mut expected = 0;
+            while data[offset] != 0 {
+                expected += data[offset] as usize;
+                offset += data[offset] as usize + 1;
+            }
+            assert_eq!(size, expected);
+        }
+
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 2, 2).write_global_palette(&[0; 6]).unwrap();
+            encoder.write_indexed_frame(2, 2, &[0, 1, 1, 0], None, &Default::default()).unwrap();
+        }
+        // Code size, a single sub-block of 3 bytes, block terminator and trailer
+        let len = data.len();
+        assert_eq!(&data[len - 7..len - 5], &[2, 3]);
+        assert_eq!(&data[len - 2..], &[0, 0x3B]);
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        decoder.read_next_frame().unwrap().unwrap();
+        assert!(decoder.read_next_frame().unwrap().is_none());
+        assert_eq!(decoder.compressed_sizes(), &[3]);
+    }
+
+    #[test]
+    fn test_plain_text() {
+        let text = PlainText {
+            left: 0,
+            top: 0,
+            width: 16,
+            height: 8,
+            cell_width: 8,
+            cell_height: 8,
+            foreground: 1,
+            background: 2,
+            text: b"A-".to_vec()
+        };
+        let mut data = Vec::new();
+        {
+            let encoder = Encoder::new(&mut data, 16, 8);
+            let mut encoder = encoder.write_global_palette(&[0; 12]).unwrap();
+            encoder.write_plain_text(&text).unwrap();
+        }
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        assert!(decoder.read_next_frame().unwrap().is_none());
+        assert_eq!(decoder.plain_texts(), &[text.clone()]);
+        let mut canvas = vec![0; 16 * 8];
+        decoder.plain_texts()[0].render(&mut canvas, 16);
+        // Top of the 'A'
+        assert_eq!(&canvas[..8], &[2, 2, 1, 1, 2, 2, 2, 2]);
+        // Cross bar of the 'A'
+        assert_eq!(&canvas[4 * 16..4 * 16 + 8], &[1, 1, 1, 1, 1, 1, 2, 2]);
+        // Dash in the second cell
+        assert_eq!(&canvas[3 * 16 + 8..3 * 16 + 16], &[1, 1, 1, 1, 1, 1, 2, 2]);
+        assert_eq!(&canvas[2 * 16 + 8..2 * 16 + 16], &[2; 8]);
+    }
+
+    #[test]
+    fn test_skip_image_data() {
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 50, 50).write_global_palette(&[0; 12]).unwrap();
+            encoder.write_loop_count(3).unwrap();
+            encoder.write_raw_extension(0xFE, b"metadata").unwrap();
+            let indices: Vec<u8> = (0..50 * 50).map(|i| (i % 3) as u8).collect();
+            for i in 0..4 {
+                let mut frame = Frame::default();
+                frame.width = 50;
+                frame.height = 50;
+                frame.delay = i * 10;
+                frame.buffer = Cow::Borrowed(&indices);
+                encoder.write_frame(&frame).unwrap();
+            }
+        }
+
+        let mut decoder = StreamingDecoder::new();
+        decoder.set(ImageData::Skip);
+        let mut buf = &*data;
+        let mut frames = 0;
+        while buf.len() > 0 {
+            let (consumed, result) = decoder.update(buf).unwrap();
+            match result {
+                Decoded::Frame(_) => frames += 1,
+                Decoded::Data(_) => panic!("image data has been decoded"),
+                _ => ()
+            }
+            buf = &buf[consumed..];
+        }
+        assert_eq!(frames, 4);
+
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(ImageData::Skip);
+        decoder.set(Extensions::Save);
+        let mut decoder = decoder.read_info().unwrap();
+        let frames = decoder.read_frame_infos().unwrap();
+        assert_eq!(frames.iter().map(|f| f.delay).collect::<Vec<_>>(), [0, 10, 20, 30]);
+        assert!(frames.iter().all(|f| f.buffer.is_empty() && (f.width, f.height) == (50, 50)));
+        assert_eq!(decoder.loop_count(), Some(3));
+        assert!(decoder.raw_extensions().iter().any(|&(label, ref data)| {
+            label == 0xFE && data == b"metadata"
+        }));
+        // Frames read with `read_next_frame` have no pixels either
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(ImageData::Skip);
+        let mut decoder = decoder.read_info().unwrap();
+        assert!(decoder.read_next_frame().unwrap().unwrap().buffer.is_empty());
+    }
+
+    #[test]
+    fn test_current_frame_is_fullscreen() {
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 4, 2).write_global_palette(&[0; 6]).unwrap();
+            encoder.write_indexed_frame(4, 2, &[0; 8], None, &Default::default()).unwrap();
+            encoder.write_indexed_frame(4, 1, &[0; 4], None, &Default::default()).unwrap();
+            let mut frame = Frame::default();
+            frame.width = 3;
+            frame.height = 2;
+            frame.left = 1;
+            frame.buffer = Cow::Owned(vec![0; 6]);
+            encoder.write_frame(&frame).unwrap();
+        }
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        decoder.read_next_frame().unwrap().unwrap();
+        assert!(decoder.current_frame_is_fullscreen());
+        decoder.read_next_frame().unwrap().unwrap();
+        assert!(!decoder.current_frame_is_fullscreen());
+        decoder.next_frame().unwrap().unwrap();
+        assert!(!decoder.current_frame_is_fullscreen());
+    }
+
+    #[test]
+    fn test_enumerate_pixels() {
+        let mut decoder = Decoder::new(File::open("tests/samples/sample_1.gif").unwrap()).read_info().unwrap();
+        let frame = decoder.read_next_frame().unwrap().unwrap();
+        let pixels: Vec<(u16, u16, u8)> = frame.enumerate_pixels().collect();
+        assert_eq!(pixels.len(), 100);
+        // Quadrants of colors 1 and 2 with a square of color 0 in the center
+        assert_eq!(pixels[0], (0, 0, 1));
+        assert_eq!(pixels[9], (9, 0, 2));
+        assert_eq!(pixels[10], (0, 1, 1));
+        assert_eq!(pixels[44], (4, 4, 0));
+        assert_eq!(pixels[57], (7, 5, 1));
+        assert_eq!(pixels[99], (9, 9, 1));
+        for (x, y, idx) in pixels {
+            assert_eq!(frame.buffer[y as usize * 10 + x as usize], idx);
+        }
+    }
+
+    #[test]
+    fn test_decode_first_frame_rgba() {
+        let (width, height, pixels) = decode_first_frame_rgba(
+            File::open("tests/samples/sample_1.gif").unwrap()
+        ).unwrap();
+        let mut decoder = Decoder::new(File::open("tests/samples/sample_1.gif").unwrap());
+        decoder.set(ColorOutput::RGBA);
+        let mut decoder = decoder.read_info().unwrap();
+        let frame = decoder.read_next_frame().unwrap().unwrap();
+        assert_eq!((width, height), (frame.width, frame.height));
+        assert_eq!(&*pixels, &*frame.buffer);
+        assert_eq!(pixels.len(), 10 * 10 * 4);
+        // Images without frames cannot provide a thumbnail
+        let mut data = Vec::new();
+        Encoder::new(&mut data, 1, 1).write_global_palette(&[0; 6]).unwrap();
+        assert!(decode_first_frame_rgba(&*data).is_err());
+    }
+
+    #[test]
+    fn test_frame_timings() {
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 1, 1).write_global_palette(&[0; 6]).unwrap();
+            let mut frame = Frame::default();
+            frame.width = 1;
+            frame.height = 1;
+            frame.buffer = Cow::Borrowed(&[0]);
+            for &delay in &[10, 0, 25, 1, 3] {
+                frame.delay = delay;
+                encoder.write_frame(&frame).unwrap();
+            }
+        }
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(ImageData::Skip);
+        let timings = decoder.read_info().unwrap().frame_timings().unwrap();
+        let ms = |ms| Duration::from_millis(ms);
+        assert_eq!(timings, vec![
+            (0, ms(0), ms(100)),
+            (1, ms(100), ms(100)),
+            (2, ms(200), ms(250)),
+            (3, ms(450), ms(100)),
+            (4, ms(550), ms(30)),
+        ]);
+    }
+
+    #[test]
+    fn test_max_frames() {
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 2, 2).write_global_palette(&[0; 6]).unwrap();
+            for i in 0..5 {
+                encoder.write_indexed_frame(2, 2, &[i % 2; 4], None, &Default::default()).unwrap();
+            }
+        }
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(MaxFrames(2));
+        let mut decoder = decoder.read_info().unwrap();
+        assert_eq!(&*decoder.read_next_frame().unwrap().unwrap().buffer, &[0; 4]);
+        assert_eq!(&*decoder.read_next_frame().unwrap().unwrap().buffer, &[1; 4]);
+        assert!(decoder.read_next_frame().unwrap().is_none());
+        assert!(decoder.next_frame().unwrap().is_none());
+
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(MaxFrames(0));
+        assert!(decoder.read_info().unwrap().read_next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_initial_canvas_rgba() {
+        let palette = &[0, 0, 0, 0xFF, 0, 0];
+        let encode = |transparent| {
+            let mut data = Vec::new();
+            {
+                let mut encoder = Encoder::new(&mut data, 3, 2);
+                encoder.set_background_color(1);
+                let mut encoder = encoder.write_global_palette(palette).unwrap();
+                let mut frame = Frame::default();
+                frame.width = 1;
+                frame.height = 1;
+                frame.transparent = transparent;
+                frame.buffer = Cow::Owned(vec![0]);
+                encoder.write_frame(&frame).unwrap();
+            }
+            data
+        };
+        let red: Vec<u8> = (0..6).flat_map(|_| vec![0xFF, 0, 0, 0xFF]).collect();
+
+        let data = encode(None);
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        assert_eq!(decoder.initial_canvas_rgba(), red);
+        decoder.next_frame().unwrap().unwrap();
+        assert_eq!(decoder.initial_canvas_rgba(), red);
+
+        // The background color is the transparent color of the first frame
+        let data = encode(Some(1));
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        decoder.next_frame().unwrap().unwrap();
+        assert_eq!(decoder.initial_canvas_rgba(), vec![0; 3 * 2 * 4]);
+
+        // Without a global palette
+        let data = b"GIF89a\x03\x00\x02\x00\x00\x01\x00\x3B";
+        let decoder = Decoder::new(&data[..]).read_info().unwrap();
+        assert_eq!(decoder.initial_canvas_rgba(), vec![0; 3 * 2 * 4]);
+    }
+
+    #[test]
+    fn test_composite_unspecified_disposal() {
+        let encode = |dispose| {
+            let mut data = Vec::new();
+            {
+                let mut encoder = Encoder::new(&mut data, 3, 3);
+                // Writes `Any` as is
+                encoder.set(DefaultDisposal(DisposalMethod::Any));
+                let mut encoder = encoder.write_global_palette(&[0, 0, 0, 0xFF, 0, 0, 0, 0xFF, 0]).unwrap();
+                let mut frame = Frame::default();
+                frame.dispose = dispose;
+                frame.delay = 5;
+                frame.width = 3;
+                frame.height = 3;
+                frame.buffer = Cow::Owned(vec![1; 9]);
+                encoder.write_frame(&frame).unwrap();
+                frame.width = 1;
+                frame.height = 1;
+                frame.left = 1;
+                frame.top = 1;
+                frame.buffer = Cow::Owned(vec![2]);
+                encoder.write_frame(&frame).unwrap();
+                frame.width = 3;
+                frame.left = 0;
+                frame.top = 2;
+                frame.transparent = Some(0);
+                frame.buffer = Cow::Owned(vec![0, 2, 0]);
+                encoder.write_frame(&frame).unwrap();
+            }
+            let mut decoder = Decoder::new(&*data).read_info().unwrap();
+            assert_eq!(decoder.read_next_frame().unwrap().unwrap().dispose, dispose);
+            let mut decoder = Decoder::new(&*data).read_info().unwrap();
+            decoder.extract_frames(FrameExtraction::Composited).unwrap()
+        };
+        let images = encode(DisposalMethod::Any);
+        assert_eq!(images, encode(DisposalMethod::Keep));
+        // The last image shows all frames on top of each other
+        let mut decoder = Decoder::new(&*images[2]);
+        decoder.set(ColorOutput::RGBA);
+        let mut decoder = decoder.read_info().unwrap();
+        let frame = decoder.read_next_frame().unwrap().unwrap();
+        let pixel = |x: usize, y: usize| frame.buffer[(y * 3 + x) * 4..][..4].to_vec();
+        assert_eq!(pixel(1, 1), [0, 0xFF, 0, 0xFF]);
+        assert_eq!(pixel(0, 2), [0xFF, 0, 0, 0xFF]);
+        assert_eq!(pixel(1, 2), [0, 0xFF, 0, 0xFF]);
+    }
+
+    #[test]
+    fn test_extract_frames() {
+        let palette = &[0, 0, 0, 0xFF, 0, 0, 0, 0xFF, 0, 0, 0, 0xFF];
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 4, 4).write_global_palette(palette).unwrap();
+            let mut frame = Frame::default();
+            frame.width = 4;
+            frame.height = 4;
+            frame.delay = 10;
+            frame.buffer = Cow::Owned(vec![1; 16]);
+            encoder.write_frame(&frame).unwrap();
+            // A green square that is cleared after being shown
+            frame.width = 2;
+            frame.height = 2;
+            frame.left = 1;
+            frame.top = 1;
+            frame.dispose = DisposalMethod::Background;
+            frame.buffer = Cow::Owned(vec![2; 4]);
+            encoder.write_frame(&frame).unwrap();
+            // A partially transparent blue line with a local palette
+            frame.width = 4;
+            frame.height = 1;
+            frame.left = 0;
+            frame.top = 3;
+            frame.dispose = DisposalMethod::Keep;
+            frame.transparent = Some(0);
+            frame.palette = Some(vec![0, 0, 0, 0, 0, 0xFF]);
+            frame.buffer = Cow::Owned(vec![1, 0, 1, 0]);
+            encoder.write_frame(&frame).unwrap();
+        }
+
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        let raw = decoder.extract_frames(FrameExtraction::Raw).unwrap();
+        assert_eq!(raw.len(), 3);
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        for image in &raw {
+            let expected = decoder.read_next_frame().unwrap().unwrap().clone();
+            let expected_palette = decoder.palette().unwrap().to_vec();
+            let mut extracted = Decoder::new(&**image).read_info().unwrap();
+            assert_eq!(&extracted.palette().unwrap()[..expected_palette.len()], &*expected_palette);
+            let frame = extracted.read_next_frame().unwrap().unwrap();
+            assert_eq!((frame.left, frame.top), (0, 0));
+            assert_eq!((frame.width, frame.height), (expected.width, expected.height));
+            assert_eq!(frame.buffer, expected.buffer);
+            assert_eq!(frame.transparent, expected.transparent);
+            assert_eq!(frame.delay, expected.delay);
+            assert!(extracted.read_next_frame().unwrap().is_none());
+        }
+
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        let composited = decoder.extract_frames(FrameExtraction::Composited).unwrap();
+        assert_eq!(composited.len(), 3);
+        let (red, green, blue) = ([0xFF, 0, 0, 0xFF], [0, 0xFF, 0, 0xFF], [0, 0, 0xFF, 0xFF]);
+        let square = |i| i == 5 || i == 6 || i == 9 || i == 10;
+        let expected = [
+            vec![red; 16],
+            (0..16).map(|i| if square(i) { green } else { red }).collect(),
+            // The square has been cleared to transparent
+            (0..16).map(|i| match i {
+                12 | 14 => blue,
+                i if square(i) => [0; 4],
+                _ => red
+            }).collect::<Vec<_>>(),
+        ];
+        for (image, expected) in composited.iter().zip(expected.iter()) {
+            let mut decoder = Decoder::new(&**image);
+            decoder.set(ColorOutput::RGBA);
+            let mut decoder = decoder.read_info().unwrap();
+            let frame = decoder.read_next_frame().unwrap().unwrap();
+            assert_eq!((frame.width, frame.height), (4, 4));
+            let expected: Vec<u8> = expected.iter().flat_map(|pix| pix.to_vec()).collect();
+            assert_eq!(&*frame.buffer, &*expected);
+        }
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(ColorOutput::RGBA);
+        assert!(decoder.read_info().unwrap().extract_frames(FrameExtraction::Raw).is_err());
+    }
+
+    #[test]
+    fn test_extract_frames_many_colors() {
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 32, 16).write_global_palette(&[]).unwrap();
+            let mut frame = Frame::default();
+            frame.width = 16;
+            frame.height = 16;
+            frame.dispose = DisposalMethod::Keep;
+            frame.buffer = Cow::Owned((0..256).map(|i| i as u8).collect());
+            // Shades of red on the left half, followed by shades of green on the right half
+            frame.palette = Some((0..256).flat_map(|i| vec![i as u8, 0, 0]).collect());
+            encoder.write_frame(&frame).unwrap();
+            frame.left = 16;
+            frame.palette = Some((0..256).flat_map(|i| vec![0, i as u8, 0]).collect());
+            encoder.write_frame(&frame).unwrap();
+        }
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        let composited = decoder.extract_frames(FrameExtraction::Composited).unwrap();
+        assert_eq!(composited.len(), 2);
+
+        // The second frame shows 511 colors without any transparency
+        let mut pixels = Vec::new();
+        for y in 0..16 {
+            for x in 0..32 {
+                let shade = (y * 16 + x % 16) as u8;
+                pixels.extend(if x < 16 { [shade, 0, 0, 0xFF] } else { [0, shade, 0, 0xFF] }.iter().cloned());
+            }
+        }
+        let quantized = Frame::from_rgba(32, 16, &mut pixels);
+        let palette = quantized.palette.as_ref().unwrap();
+        let expected: Vec<u8> = quantized.buffer.iter().flat_map(|&index| {
+            let rgb = &palette[index as usize * 3..][..3];
+            vec![rgb[0], rgb[1], rgb[2], 0xFF]
+        }).collect();
+        let mut decoder = Decoder::new(&*composited[1]);
+        decoder.set(ColorOutput::RGBA);
+        let mut decoder = decoder.read_info().unwrap();
+        let frame = decoder.read_next_frame().unwrap().unwrap();
+        assert_eq!((frame.width, frame.height), (32, 16));
+        assert_eq!(&*frame.buffer, &*expected);
+    }
+
+    #[test]
+    fn test_local_palette_size_mismatch() {
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 2, 2).write_global_palette(&[]).unwrap();
+            encoder.write_indexed_frame(2, 2, &[0, 1, 1, 0], Some(&[0, 0, 0, 0xFF, 0xFF, 0xFF]), &Default::default()).unwrap();
+        }
+        // Header with the padded global palette (19), control extension (8)
+        // and image descriptor (10)
+        assert_eq!(data[36] & 0b1000_0111, 0b1000_0000);
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        assert!(decoder.read_next_frame().unwrap().is_some());
+        // Two entries are declared but the table holds four
+        data.splice(43..43, vec![0xFF; 6]);
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        match decoder.read_next_frame() {
+            Err(DecodingError::Format("local color table size does not match its data")) => (),
+            result => panic!("unexpected result {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_invalid_min_code_size() {
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 2, 2).write_global_palette(&[0; 6]).unwrap();
+            encoder.write_indexed_frame(2, 2, &[0, 1, 1, 0], None, &Default::default()).unwrap();
+        }
+        // Header with the global palette (19), control extension (8) and image descriptor (10)
+        assert_eq!(data[37], 2);
+        for &size in &[8, 9] {
+            data[37] = size;
+            let mut decoder = Decoder::new(&*data).read_info().unwrap();
+            // A code size of 8 is valid, the data just does not match it
+            match decoder.read_next_frame() {
+                Err(DecodingError::Format("invalid minimum code size")) => assert_eq!(size, 9),
+                _ => assert_eq!(size, 8)
+            }
+        }
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let mut data = Vec::new();
+        File::open("tests/samples/sample_1.gif").unwrap().read_to_end(&mut data).unwrap();
+        data[3..6].copy_from_slice(b"90a");
+        match Decoder::new(&*data).read_info() {
+            Err(DecodingError::UnsupportedVersion(ref version)) if version == b"90a" => (),
+            Err(err) => panic!("unexpected error {:?}", err),
+            Ok(_) => panic!("unsupported version was accepted")
+        }
+        match Decoder::new(&b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR"[..]).read_info() {
+            Err(DecodingError::Format("not a GIF file")) => (),
+            Err(err) => panic!("unexpected error {:?}", err),
+            Ok(_) => panic!("PNG signature was accepted")
+        }
+    }
+
+    #[test]
+    fn test_signature_scan() {
+        let mut gif = Vec::new();
+        File::open("tests/samples/sample_1.gif").unwrap().read_to_end(&mut gif).unwrap();
+        let expected = Decoder::new(&*gif).read_info().unwrap().read_next_frame().unwrap().unwrap().buffer.to_vec();
+        // Byte order mark and a stray 'G'
+        let mut data = b"\xEF\xBB\xBFG\n".to_vec();
+        data.extend(gif.iter().cloned());
+        assert!(Decoder::new(&*data).read_info().is_err());
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(SignatureScan(4));
+        assert!(decoder.read_info().is_err());
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(SignatureScan(5));
+        let mut decoder = decoder.read_info().unwrap();
+        assert_eq!(&*decoder.read_next_frame().unwrap().unwrap().buffer, &*expected);
+        assert!(decoder.read_next_frame().unwrap().is_none());
+        // Offsets count the skipped bytes
+        assert_eq!(decoder.frame_ranges()[0].start, 5 + 33);
+
+        // A signature of an unknown version is not skipped as junk
+        let mut data = b"\n".to_vec();
+        data.extend(b"GIF90a".iter().cloned());
+        data.extend(gif[6..].iter().cloned());
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(SignatureScan(16));
+        match decoder.read_info() {
+            Err(DecodingError::UnsupportedVersion(version)) => assert_eq!(&version, b"90a"),
+            Err(err) => panic!("unexpected error {:?}", err),
+            Ok(_) => panic!("unsupported version accepted")
+        }
+    }
+
+    #[test]
+    fn test_extension_limit() {
+        let comment = vec![b'x'; 4 << 20];
+        let mut data = Vec::new();
+        {
+            let encoder = Encoder::new(&mut data, 1, 1);
+            let mut encoder = encoder.write_global_palette(&[0; 6]).unwrap();
+            encoder.write_raw_extension(0xFE, &comment).unwrap();
+            encoder.write_indexed_frame(1, 1, &[0], None, &Default::default()).unwrap();
+        }
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(ExtensionLimit(1 << 20));
+        let mut decoder = decoder.read_info().unwrap();
+        match decoder.read_next_frame() {
+            Err(DecodingError::Format("extension exceeds the size limit")) => (),
+            result => panic!("unexpected result {:?}", result)
+        }
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        assert!(decoder.read_next_frame().unwrap().is_some());
+    }
+
+    #[test]
+    fn test_screen_descriptor_flags() {
+        let mut data = Vec::new();
+        File::open("tests/samples/sample_1.gif").unwrap().read_to_end(&mut data).unwrap();
+        let decoder = Decoder::new(&*data).read_info().unwrap();
+        assert_eq!(decoder.screen_descriptor_flags(), data[10]);
+        // Color resolution and sort flag are kept as well, the table size is unchanged
+        data[10] = 0b1101_1001;
+        let decoder = Decoder::new(&*data).read_info().unwrap();
+        assert_eq!(decoder.screen_descriptor_flags(), 0b1101_1001);
+        assert!(decoder.global_palette_sorted());
+    }
+
+    #[test]
+    fn test_control_flags() {
+        let mut data = Vec::new();
+        File::open("tests/samples/anim-gr.gif").unwrap().read_to_end(&mut data).unwrap();
+        let pos = data.windows(3).position(|w| w == [0x21, 0xF9, 0x04]).unwrap();
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        decoder.next_frame().unwrap().unwrap();
+        assert_eq!(decoder.current_control_flags(), Some(data[pos + 3]));
+    }
+
+    #[test]
+    fn test_frame_without_control_extension() {
+        let mut data = Vec::new();
+        data.extend(b"GIF89a\x01\x00\x01\x00\x80\x00\x00".iter().cloned());
+        data.extend([0, 0, 0, 0xFF, 0xFF, 0xFF].iter().cloned());
+        // Control extension with delay 10 and transparent index 0
+        data.extend([0x21, 0xF9, 4, 0b0000_1001, 10, 0, 0, 0].iter().cloned());
+        for _ in 0..2 {
+            data.extend([0x2C, 0, 0, 0, 0, 1, 0, 1, 0, 0].iter().cloned());
+            // 3 bit codes: clear, 0, end
+            data.extend([2, 2, 0x44, 0x01, 0].iter().cloned());
+        }
+        data.push(0x3B);
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        {
+            let frame = decoder.read_next_frame().unwrap().unwrap();
+            assert_eq!((frame.delay, frame.transparent), (10, Some(0)));
+        }
+        {
+            let frame = decoder.read_next_frame().unwrap().unwrap();
+            assert_eq!((frame.delay, frame.transparent), (0, None));
+        }
+        assert_eq!(decoder.current_control_flags(), None);
+    }
+
+    #[test]
+    fn test_mixed_palettes() {
+        let global = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+        let local = [13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24];
+        let mut data = Vec::new();
+        {
+            let encoder = Encoder::new(&mut data, 1, 1);
+            let mut encoder = encoder.write_global_palette(&global).unwrap();
+            encoder.write_indexed_frame(1, 1, &[2], None, &Default::default()).unwrap();
+            encoder.write_indexed_frame(1, 1, &[2], Some(&local), &Default::default()).unwrap();
+            encoder.write_indexed_frame(1, 1, &[3], None, &Default::default()).unwrap();
+        }
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(ColorOutput::RGBA);
+        let mut decoder = decoder.read_info().unwrap();
+        assert_eq!(decoder.palette().unwrap(), &global[..]);
+        for &(palette, rgba) in &[
+            (&global, [7, 8, 9, 0xFF]),
+            (&local, [19, 20, 21, 0xFF]),
+            (&global, [10, 11, 12, 0xFF])
+        ] {
+            assert_eq!(&*decoder.read_next_frame().unwrap().unwrap().buffer, &rgba[..]);
+            assert_eq!(decoder.palette().unwrap(), &palette[..]);
+        }
+    }
+
+    #[test]
+    fn test_repeated_local_palette() {
+        use std::rc::Rc;
+        let local = [13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24];
+        let mut data = Vec::new();
+        {
+            let encoder = Encoder::new(&mut data, 1, 1);
+            let mut encoder = encoder.write_global_palette(&[0; 12]).unwrap();
+            encoder.write_indexed_frame(1, 1, &[2], Some(&local), &Default::default()).unwrap();
+            encoder.write_indexed_frame(1, 1, &[3], Some(&local), &Default::default()).unwrap();
+            let mut opts = FrameOptions::default();
+            opts.transparent = Some(3);
+            encoder.write_indexed_frame(1, 1, &[3], Some(&local), &opts).unwrap();
+            encoder.write_indexed_frame(1, 1, &[3], None, &Default::default()).unwrap();
+        }
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(ColorOutput::RGBA);
+        let mut decoder = decoder.read_info().unwrap();
+        let mut palettes = Vec::new();
+        for rgba in &[[19, 20, 21, 0xFF], [22, 23, 24, 0xFF], [22, 23, 24, 0], [0, 0, 0, 0xFF]] {
+            assert_eq!(&*decoder.read_next_frame().unwrap().unwrap().buffer, &rgba[..]);
+            palettes.push(decoder.palette.clone().unwrap());
+        }
+        // The local palette is shared as long as it does not change
+        assert!(Rc::ptr_eq(&palettes[0], &palettes[1]));
+        assert!(Rc::ptr_eq(&palettes[0], &palettes[2]));
+        assert!(!Rc::ptr_eq(&palettes[0], &palettes[3]));
+    }
+
+    #[test]
+    fn test_transcode_unknown_extension() {
+        let mut data = Vec::new();
+        {
+            let encoder = Encoder::new(&mut data, 1, 1);
+            let mut encoder = encoder.write_global_palette(&[0; 12]).unwrap();
+            encoder.write_raw_extension(0x42, b"future").unwrap();
+            encoder.write_indexed_frame(1, 1, &[3], None, &Default::default()).unwrap();
+        }
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(Extensions::Save);
+        let mut decoder = decoder.read_info().unwrap();
+        while let Some(_) = decoder.read_next_frame().unwrap() {}
+        assert_eq!(decoder.raw_extensions()[0], (0x42, b"future".to_vec()));
+        let mut transcoded = Vec::new();
+        {
+            let encoder = Encoder::new(&mut transcoded, 1, 1);
+            let mut encoder = encoder.write_global_palette(&[0; 12]).unwrap();
+            for &(label, ref content) in decoder.raw_extensions() {
+                if label == 0x42 {
+                    encoder.write_raw_extension(label, content).unwrap();
+                }
+            }
+            encoder.write_indexed_frame(1, 1, &[3], None, &Default::default()).unwrap();
+        }
+        assert_eq!(transcoded, data);
+    }
+
+    #[test]
+    fn test_transcode_extension_sub_blocks() {
+        let mut frame_data = Vec::new();
+        {
+            let encoder = Encoder::new(&mut frame_data, 1, 1);
+            let mut encoder = encoder.write_global_palette(&[0; 12]).unwrap();
+            encoder.write_indexed_frame(1, 1, &[3], None, &Default::default()).unwrap();
+        }
+        // Application extension with unusual chunking, inserted after the global color table
+        let mut extension = vec![0x21, 0xFF, 11];
+        extension.extend(b"EXAMPLE_APP".iter().cloned());
+        extension.extend([3, 1, 2, 3, 1, 4].iter().cloned());
+        extension.push(200);
+        extension.extend((0..200).map(|i| i as u8));
+        extension.push(0);
+        let mut data = frame_data[..13 + 12].to_vec();
+        data.extend(extension.iter().cloned());
+        data.extend(frame_data[13 + 12..].iter().cloned());
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(Extensions::Save);
+        let mut decoder = decoder.read_info().unwrap();
+        while let Some(_) = decoder.read_next_frame().unwrap() {}
+        let extensions = decoder.raw_extension_sub_blocks();
+        assert_eq!(extensions.len(), 2);
+        let lengths: Vec<usize> = extensions[0].1.iter().map(|block| block.len()).collect();
+        assert_eq!((extensions[0].0, lengths), (0xFF, vec![11, 3, 1, 200]));
+        // The control extension consists of a single sub-block
+        assert_eq!(extensions[1].0, 0xF9);
+        assert_eq!(extensions[1].1.len(), 1);
+        let mut transcoded = Vec::new();
+        {
+            let encoder = Encoder::new(&mut transcoded, 1, 1);
+            let mut encoder = encoder.write_global_palette(&[0; 12]).unwrap();
+            for &(label, ref sub_blocks) in &extensions {
+                if label == 0xFF {
+                    encoder.write_extension_sub_blocks(label, sub_blocks).unwrap();
+                }
+            }
+            encoder.write_indexed_frame(1, 1, &[3], None, &Default::default()).unwrap();
+            // An empty sub-block would terminate the extension
+            assert!(encoder.write_extension_sub_blocks(0xFF, &[vec![]]).is_err());
+        }
+        assert_eq!(transcoded, data);
+    }
+
+    #[test]
+    fn test_into_inner() {
+        let mut data = Vec::new();
+        File::open("tests/samples/sample_1.gif").unwrap().read_to_end(&mut data).unwrap();
+        data.extend(b"trailing data".iter().cloned());
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        while let Some(_) = decoder.read_next_frame().unwrap() {}
+        // The trailer is accounted for by the decoder
+        let end = data.len() - b"trailing data".len();
+        assert_eq!(decoder.decoder.decoder.bytes_consumed(), end);
+        assert_eq!(decoder.decoder.decoder.block_offset(), end - 1);
+        let mut rest = Vec::new();
+        decoder.into_inner().read_to_end(&mut rest).unwrap();
+        assert_eq!(&*rest, b"trailing data");
+    }
+
+    #[test]
+    fn test_interlaced_sorted_local_palette() {
+        let palette = [1, 1, 1, 2, 2, 2, 3, 3, 3, 4, 4, 4];
+        let mut data = Vec::new();
+        {
+            let encoder = Encoder::new(&mut data, 1, 4);
+            let mut encoder = encoder.write_global_palette(&[]).unwrap();
+            // Lines in interlaced order: 0, 2, 1, 3
+            encoder.write_indexed_frame(1, 4, &[0, 2, 1, 3], Some(&palette), &Default::default()).unwrap();
+        }
+        // Set the interlace and sort flags in addition to the local table flag
+        let flags = data.iter().position(|&b| b == 0x2C).unwrap() + 9;
+        assert_eq!(data[flags] & 0b1110_0000, 0b1000_0000);
+        data[flags] |= 0b0110_0000;
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        {
+            let frame = decoder.read_next_frame().unwrap().unwrap();
+            assert!(frame.interlaced);
+            assert_eq!(&*frame.buffer, &[0, 1, 2, 3][..]);
+        }
+        assert!(decoder.current_palette_sorted());
+        assert_eq!(decoder.palette().unwrap(), &palette[..]);
+    }
+
+    #[test]
+    fn test_take_current_frame() {
+        let mut data = Vec::new();
+        File::open("tests/samples/anim-gr.gif").unwrap().read_to_end(&mut data).unwrap();
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        let mut frames = Vec::new();
+        loop {
+            let ptr = match decoder.read_next_frame().unwrap() {
+                Some(frame) => frame.buffer.as_ptr(),
+                None => break
+            };
+            let frame = decoder.take_current_frame();
+            assert_eq!(frame.buffer.as_ptr(), ptr);
+            frames.push(frame);
+        }
+        assert_eq!(frames.len(), 2);
+    }
+
+    #[test]
+    fn test_fill_buffer_u16() {
+        let mut data = Vec::new();
+        File::open("tests/samples/anim-gr.gif").unwrap().read_to_end(&mut data).unwrap();
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(ColorOutput::Indexed);
+        let mut decoder = decoder.read_info().unwrap();
+        let indices = decoder.read_next_frame().unwrap().unwrap().buffer.to_vec();
+        // Read in small chunks to exercise the internal buffering
+        for color_output in vec![ColorOutput::Indexed, ColorOutput::RGBA] {
+            let mut decoder = Decoder::new(&*data);
+            decoder.set(color_output);
+            let mut decoder = decoder.read_info().unwrap();
+            decoder.next_frame().unwrap().unwrap();
+            let mut widened = vec![0u16; indices.len()];
+            for chunk in widened.chunks_mut(7) {
+                assert!(decoder.fill_buffer_u16(chunk).unwrap());
+            }
+            let expected: Vec<u16> = indices.iter().map(|&idx| idx as u16).collect();
+            assert_eq!(widened, expected);
+        }
+    }
+
+    #[test]
+    fn test_buffered_len() {
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 6, 1).write_global_palette(&[0; 12]).unwrap();
+            encoder.write_indexed_frame(6, 1, &[1; 6], None, &Default::default()).unwrap();
+        }
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(ColorOutput::Indexed);
+        let mut decoder = decoder.read_info().unwrap();
+        decoder.next_frame().unwrap().unwrap();
+        assert_eq!(decoder.buffered_len(), 0);
+        // The LZW codes decode to runs of 1, 2 and 3 pixels
+        let mut buf = [0; 4];
+        assert!(decoder.fill_buffer(&mut buf).unwrap());
+        assert_eq!(buf, [1; 4]);
+        assert_eq!(decoder.buffered_len(), 2);
+        let mut buf = [0; 2];
+        assert!(decoder.fill_buffer_u16(&mut buf).unwrap());
+        assert_eq!(buf, [1; 2]);
+        assert_eq!(decoder.buffered_len(), 0);
+    }
+
+    #[test]
+    fn test_progress_callback() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+        let mut data = Vec::new();
+        File::open("tests/samples/anim-gr.gif").unwrap().read_to_end(&mut data).unwrap();
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        {
+            let calls = calls.clone();
+            decoder.set_progress_callback(move |frames, bytes| calls.borrow_mut().push((frames, bytes)));
+        }
+        let frames = decoder.read_all_frames().unwrap();
+        assert_eq!(frames.len(), 2);
+        let calls = calls.borrow();
+        assert_eq!(calls.len(), 2);
+        // The last bytes of the image data might not have been consumed yet
+        for (i, (&(frames, bytes), range)) in calls.iter().zip(decoder.frame_ranges()).enumerate() {
+            assert_eq!(frames, i + 1);
+            assert!(range.start < bytes && bytes <= range.end);
+        }
+    }
+
+    #[test]
+    fn test_palette_cache() {
+        let palette = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 2, 2).write_global_palette(&palette).unwrap();
+            let mut frame = Frame::default();
+            frame.width = 2;
+            frame.height = 2;
+            frame.buffer = Cow::Borrowed(&[0, 1, 2, 3]);
+            frame.transparent = Some(1);
+            encoder.write_frame(&frame).unwrap();
+            frame.transparent = None;
+            encoder.write_frame(&frame).unwrap();
+            frame.palette = Some(vec![0xFF; 12]);
+            encoder.write_frame(&frame).unwrap();
+        }
+        let decode = |table: Option<[[u8; 4]; 256]>| {
+            let mut decoder = Decoder::new(&*data);
+            decoder.set(ColorOutput::RGBA);
+            let mut decoder = decoder.read_info().unwrap();
+            if let Some(table) = table {
+                decoder.set_palette_cache(table);
+            }
+            decoder.read_all_frames().unwrap().into_iter().map(|frame| frame.buffer.into_owned()).collect::<Vec<_>>()
+        };
+        let mut table = [[0; 4]; 256];
+        for (rgba, rgb) in table.iter_mut().zip(palette.chunks(3)) {
+            *rgba = [rgb[0], rgb[1], rgb[2], 0xFF];
+        }
+        let expected = decode(None);
+        assert_eq!(decode(Some(table)), expected);
+        // The table replaces the global palette only
+        table[0] = [0, 0, 0, 0xFF];
+        let frames = decode(Some(table));
+        assert_eq!(&frames[1][..4], &[0, 0, 0, 0xFF]);
+        assert_eq!(&frames[1][4..], &expected[1][4..]);
+        assert_eq!(frames[2], expected[2]);
+        // The transparent index of the first frame does not leak into the second one
+        assert_eq!(frames[0][7], 0);
+        assert_eq!(frames[1][7], 0xFF);
+    }
+
+    #[test]
+    fn test_packed_indexed() {
+        let indices = [0, 1, 2, 3, 3, 2, 1, 0, 1, 1];
+        let mut data = Vec::new();
+        {
+            let encoder = Encoder::new(&mut data, 5, 2);
+            let mut encoder = encoder.write_global_palette(&[0; 12]).unwrap();
+            encoder.write_indexed_frame(5, 2, &indices, None, &Default::default()).unwrap();
+        }
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        assert_eq!(&*decoder.read_next_frame().unwrap().unwrap().buffer, &indices[..]);
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(ColorOutput::PackedIndexed);
+        let mut decoder = decoder.read_info().unwrap();
+        let packed = decoder.read_next_frame().unwrap().unwrap().buffer.to_vec();
+        assert_eq!(decoder.packed_bit_depth(), 2);
+        assert_eq!(decoder.packed_line_length(), 2);
+        assert_eq!(packed, vec![0b00_01_10_11, 0b11_000000, 0b10_01_00_01, 0b01_000000]);
+
+        // `fill_buffer` returns the whole frame unpacked
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(ColorOutput::PackedIndexed);
+        let mut decoder = decoder.read_info().unwrap();
+        decoder.next_frame().unwrap().unwrap();
+        assert_eq!(decoder.line_length(), 5);
+        let mut buf = vec![0; decoder.buffer_size()];
+        assert!(decoder.fill_buffer(&mut buf).unwrap());
+        assert_eq!(buf, indices);
+
+        // Indices outside of the palette are not truncated
+        let mut data = Vec::new();
+        {
+            let encoder = Encoder::new(&mut data, 2, 1);
+            let mut encoder = encoder.write_global_palette(&[0; 12]).unwrap();
+            encoder.write_indexed_frame(2, 1, &[1, 5], None, &Default::default()).unwrap();
+        }
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(ColorOutput::PackedIndexed);
+        let mut decoder = decoder.read_info().unwrap();
+        match decoder.read_next_frame() {
+            Err(DecodingError::Format("index does not fit into the packed bit depth")) => (),
+            result => panic!("unexpected result {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_oversized_frame() {
+        let mut data = Vec::new();
+        {
+            let mut frame = Frame::default();
+            frame.width = 3;
+            frame.height = 2;
+            frame.buffer = Cow::Borrowed(&[0, 1, 2, 3, 2, 1]);
+            let encoder = Encoder::new(&mut data, 3, 3);
+            let mut encoder = encoder.write_global_palette(&[0; 12]).unwrap();
+            encoder.write_frame(&frame).unwrap();
+        }
+        // The encoder rejects oversized frames, move the frame afterwards
+        let pos = data.iter().position(|&b| b == 0x2C).unwrap();
+        data[pos + 1] = 1;
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        {
+            let frame = decoder.read_next_frame().unwrap().unwrap();
+            assert_eq!((frame.left, frame.width, frame.height), (1, 2, 2));
+            assert_eq!(&*frame.buffer, &[0, 1, 3, 2][..]);
+        }
+        let mut decoder = Decoder::new(&*data);
+        decoder.set(FrameBounds::Error);
+        let mut decoder = decoder.read_info().unwrap();
+        assert!(decoder.read_next_frame().is_err());
+    }
+
+    #[test]
+    fn test_empty_screen() {
+        let mut data = Vec::new();
+        {
+            let mut frame = Frame::default();
+            frame.left = 1;
+            frame.width = 3;
+            frame.height = 2;
+            frame.buffer = Cow::Borrowed(&[0, 1, 2, 3, 2, 1]);
+            let encoder = Encoder::new(&mut data, 4, 2);
+            let mut encoder = encoder.write_global_palette(&[0; 12]).unwrap();
+            encoder.write_frame(&frame).unwrap();
+        }
+        // The encoder rejects an empty logical screen, clear it afterwards
+        for b in &mut data[6..10] {
+            *b = 0
+        }
+        for &bounds in &[FrameBounds::Clamp, FrameBounds::Error] {
+            let mut decoder = Decoder::new(&*data);
+            decoder.set(bounds);
+            let mut decoder = decoder.read_info().unwrap();
+            assert_eq!((decoder.width(), decoder.height()), (0, 0));
+            let frame = decoder.read_next_frame().unwrap().unwrap();
+            assert_eq!((frame.left, frame.width, frame.height), (1, 3, 2));
+            assert_eq!(&*frame.buffer, &[0, 1, 2, 3, 2, 1][..]);
+        }
+    }
+
+    #[test]
+    fn test_local_palette() {
+        let mut data = Vec::new();
+        {
+            let mut frame = Frame::default();
+            frame.width = 2;
+            frame.height = 2;
+            frame.buffer = Cow::Borrowed(&[0, 1, 2, 3]);
+            let encoder = Encoder::new(&mut data, 2, 2);
+            let mut encoder = encoder.write_global_palette(&[0; 12]).unwrap();
+            encoder.write_frame(&frame).unwrap();
+            frame.palette = Some(vec![0xFF; 12]);
+            encoder.write_frame(&frame).unwrap();
+        }
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        decoder.read_next_frame().unwrap().unwrap();
+        assert!(!decoder.current_frame_has_local_palette());
+        decoder.read_next_frame().unwrap().unwrap();
+        assert!(decoder.current_frame_has_local_palette());
+    }
+
+    #[test]
+    fn test_frames_bounding_box() {
+        let mut data = Vec::new();
+        {
+            let encoder = Encoder::new(&mut data, 4, 4);
+            let mut encoder = encoder.write_global_palette(&[0; 12]).unwrap();
+            let mut frame = Frame::default();
+            frame.left = 1;
+            frame.top = 1;
+            frame.width = 2;
+            frame.height = 2;
+            frame.buffer = Cow::Borrowed(&[0, 1, 2, 3]);
+            encoder.write_frame(&frame).unwrap();
+            frame.left = 3;
+            frame.top = 0;
+            frame.width = 1;
+            frame.buffer = Cow::Borrowed(&[0, 1]);
+            encoder.write_frame(&frame).unwrap();
+        }
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        assert_eq!(decoder.frames_bounding_box().unwrap(), Some((1, 0, 3, 3)));
+        // Corrupt image data is not decompressed, header (25), control extension (8),
+        // image descriptor (10), code size and sub-block length precede it
+        data[45] = 0xFF;
+        assert!(Decoder::new(&*data).read_info().unwrap().read_next_frame().is_err());
+        let mut decoder = Decoder::new(&*data).read_info().unwrap();
+        assert_eq!(decoder.frames_bounding_box().unwrap(), Some((1, 0, 3, 3)));
+        assert_eq!(decoder.decoder.decoder.image_data(), ImageData::Decode);
+    }
+
+    /// Encodes every pixel as a literal code without ever clearing the dictionary
+    ///
+    /// Once the decoder's dictionary holds 4096 entries the code size stays at 12 bits
+    /// and no more entries are added (a "deferred clear").
+    fn lzw_literals_deferred_clear(data: &[u8], min_code_size: u8) -> Vec<u8> {
+        let clear_code = 1u32 << min_code_size;
+        let mut code_size = min_code_size + 1;
+        let mut next_code = clear_code + 2;
+        let mut result = Vec::new();
+        let mut acc = 0u32;
+        let mut bits = 0;
+        {
+            let mut write_code = |code: u32, code_size: u8| {
+                acc |= code << bits;
+                bits += code_size;
+                while bits >= 8 {
+                    result.push(acc as u8);
+                    acc >>= 8;
+                    bits -= 8;
+                }
+            };
+            write_code(clear_code, code_size);
+            for (i, &idx) in data.iter().enumerate() {
+                write_code(idx as u32, code_size);
+                // Every code after the first one adds an entry
+                if i > 0 && next_code < 4096 {
+                    next_code += 1;
+                    if next_code == 1 << code_size && code_size < 12 {
+                        code_size += 1;
+                    }
+                }
+            }
+            write_code(clear_code + 1, code_size);
+        }
+        if bits > 0 {
+            result.push(acc as u8);
+        }
+        result
+    }
+
+    #[test]
+    fn test_full_lzw_dictionary() {
+        use encoder::Compression;
+        // Pseudo-random pixels need far more than 4096 codes
+        let mut seed = 1u32;
+        let indices: Vec<u8> = (0..200 * 100).map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as u8
+        }).collect();
+        for &compression in &[Compression::Lzw, Compression::Custom(lzw_literals_deferred_clear)] {
+            let mut data = Vec::new();
+            {
+                let mut encoder = Encoder::new(&mut data, 200, 100);
+                encoder.set(compression);
+                let mut encoder = encoder.write_global_palette(&[0; 3 * 256]).unwrap();
+                encoder.write_indexed_frame(200, 100, &indices, None, &Default::default()).unwrap();
+            }
+            let mut decoder = Decoder::new(&*data).read_info().unwrap();
+            assert_eq!(&*decoder.read_next_frame().unwrap().unwrap().buffer, &*indices);
+            assert!(decoder.read_next_frame().unwrap().is_none());
+        }
+    }
 }
 
 
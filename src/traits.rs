@@ -65,4 +65,92 @@ impl<W: io::Write + ?Sized> WriteBytesExt<u64> for W {
 		self.write_le((n >> 32) as u32)
 		
 	}
-}
\ No newline at end of file
+}
+
+/// Assembles a `u16` from its bytes. Least significant byte first.
+///
+/// Allows the streaming decoder to combine bytes that arrived in different buffers.
+#[inline]
+pub fn u16_from_le(bytes: [u8; 2]) -> u16 {
+	bytes[0] as u16 | (bytes[1] as u16) << 8
+}
+
+/// Reader extension to read little endian data
+pub trait ReadBytesExt<T> {
+	/// Reads `T` from a bytes stream. Least significant byte first.
+	fn read_le(&mut self) -> io::Result<T>;
+}
+
+impl<R: io::Read + ?Sized> ReadBytesExt<u8> for R {
+	#[inline]
+	fn read_le(&mut self) -> io::Result<u8> {
+		let mut buf = [0; 1];
+		try!(self.read_exact(&mut buf));
+		Ok(buf[0])
+	}
+}
+
+impl<R: io::Read + ?Sized> ReadBytesExt<u16> for R {
+	#[inline]
+	fn read_le(&mut self) -> io::Result<u16> {
+		let mut buf = [0; 2];
+		try!(self.read_exact(&mut buf));
+		Ok(u16_from_le(buf))
+	}
+}
+
+impl<R: io::Read + ?Sized> ReadBytesExt<u32> for R {
+	#[inline]
+	fn read_le(&mut self) -> io::Result<u32> {
+		let low: u16 = try!(self.read_le());
+		let high: u16 = try!(self.read_le());
+		Ok(low as u32 | (high as u32) << 16)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::io::prelude::*;
+
+	use super::{ReadBytesExt, WriteBytesExt, u16_from_le};
+
+	#[test]
+	fn test_u16_from_le() {
+		assert_eq!(u16_from_le([0x34, 0x12]), 0x1234);
+		assert_eq!(u16_from_le([0xFF, 0x00]), 0x00FF);
+	}
+
+	#[test]
+	fn test_read_le() {
+		let mut data = &[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07][..];
+		assert_eq!(ReadBytesExt::<u8>::read_le(&mut data).unwrap(), 0x01);
+		assert_eq!(ReadBytesExt::<u16>::read_le(&mut data).unwrap(), 0x0302);
+		assert_eq!(ReadBytesExt::<u32>::read_le(&mut data).unwrap(), 0x07060504);
+		assert!(ReadBytesExt::<u8>::read_le(&mut data).is_err());
+	}
+
+	#[test]
+	fn test_read_le_split() {
+		// The value is split between two buffers at every possible position
+		let bytes = [0x78, 0x56, 0x34, 0x12];
+		for i in 0..bytes.len() + 1 {
+			let mut r = bytes[..i].chain(&bytes[i..]);
+			assert_eq!(ReadBytesExt::<u32>::read_le(&mut r).unwrap(), 0x12345678);
+			let mut r = bytes[..i].chain(&bytes[i..]);
+			assert_eq!(ReadBytesExt::<u16>::read_le(&mut r).unwrap(), 0x5678);
+		}
+		// Truncated input
+		let mut r = bytes[..1].chain(&bytes[1..3]);
+		assert!(ReadBytesExt::<u32>::read_le(&mut r).is_err());
+	}
+
+	#[test]
+	fn test_round_trip() {
+		let mut data = Vec::new();
+		data.write_le(0xBEEFu16).unwrap();
+		data.write_le(0xDEADBEEFu32).unwrap();
+		let mut r = &*data;
+		assert_eq!(ReadBytesExt::<u16>::read_le(&mut r).unwrap(), 0xBEEF);
+		assert_eq!(ReadBytesExt::<u32>::read_le(&mut r).unwrap(), 0xDEADBEEF);
+	}
+}